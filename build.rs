@@ -1,8 +1,12 @@
-#[cfg(not(feature = "riscv-ulp-hal"))]
+#[cfg(all(feature = "esp-idf-sys", not(feature = "riscv-ulp-hal")))]
 fn main() -> anyhow::Result<()> {
     embuild::build::CfgArgs::output_propagated("ESP_IDF")
 }
 
+// Host build of the hardware independent modules, see `src/lib.rs`
+#[cfg(not(any(feature = "esp-idf-sys", feature = "riscv-ulp-hal")))]
+fn main() {}
+
 #[cfg(feature = "riscv-ulp-hal")]
 fn main() {
     println!("cargo:rustc-cfg=esp32s2");
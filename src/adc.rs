@@ -1,3 +1,5 @@
+#[cfg(not(feature = "riscv-ulp-hal"))]
+use core::convert::TryFrom;
 use core::marker::PhantomData;
 
 #[cfg(not(feature = "riscv-ulp-hal"))]
@@ -9,6 +11,9 @@ use crate::riscv_ulp_hal::sys::*;
 #[cfg(all(esp32, not(feature = "riscv-ulp-hal")))]
 use crate::hall;
 
+#[cfg(not(feature = "riscv-ulp-hal"))]
+use crate::gpio::ADCPin;
+
 pub trait Adc: Send {
     fn unit() -> adc_unit_t;
 }
@@ -57,11 +62,16 @@ impl<ADC: Adc> Analog<ADC> for Atten11dB<ADC> {
     }
 }
 
+#[cfg(not(feature = "riscv-ulp-hal"))]
+pub mod calibration;
+
 /// ADC configuration
 #[cfg(not(feature = "riscv-ulp-hal"))]
 pub mod config {
     use esp_idf_sys::*;
 
+    use super::calibration::UserCalibration;
+
     /// The sampling/readout resolution of the ADC
     #[derive(Debug, PartialEq, Eq, Clone, Copy)]
     pub enum Resolution {
@@ -89,6 +99,24 @@ pub mod config {
         }
     }
 
+    impl Resolution {
+        /// Largest raw reading at this resolution
+        pub fn max_reading(&self) -> u16 {
+            match self {
+                #[cfg(esp32)]
+                Resolution::Resolution9Bit => 511,
+                #[cfg(esp32)]
+                Resolution::Resolution10Bit => 1023,
+                #[cfg(esp32)]
+                Resolution::Resolution11Bit => 2047,
+                #[cfg(any(esp32, esp32c3, esp32s3))]
+                Resolution::Resolution12Bit => 4095,
+                #[cfg(esp32s2)]
+                Resolution::Resolution13Bit => 8191,
+            }
+        }
+    }
+
     impl From<Resolution> for adc_bits_width_t {
         fn from(resolution: Resolution) -> Self {
             match resolution {
@@ -106,10 +134,121 @@ pub mod config {
         }
     }
 
-    #[derive(Debug, Copy, Clone, Default)]
+    /// Input attenuation, which selects the measurable voltage range
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum Attenuation {
+        DB0,
+        DB2_5,
+        DB6,
+        DB11,
+    }
+
+    impl Default for Attenuation {
+        fn default() -> Self {
+            Self::DB11
+        }
+    }
+
+    impl Attenuation {
+        /// Voltage of the full-scale reading, used when the ADC is not calibrated
+        #[cfg(esp32)]
+        pub fn max_mv(&self) -> u16 {
+            match self {
+                Self::DB0 => 950,
+                Self::DB2_5 => 1250,
+                Self::DB6 => 1750,
+                Self::DB11 => 2450,
+            }
+        }
+
+        /// Voltage of the full-scale reading, used when the ADC is not calibrated
+        #[cfg(any(esp32c3, esp32s2))]
+        pub fn max_mv(&self) -> u16 {
+            match self {
+                Self::DB0 => 750,
+                Self::DB2_5 => 1050,
+                Self::DB6 => 1300,
+                Self::DB11 => 2500,
+            }
+        }
+
+        /// Voltage of the full-scale reading, used when the ADC is not calibrated
+        #[cfg(esp32s3)]
+        pub fn max_mv(&self) -> u16 {
+            match self {
+                Self::DB0 => 950,
+                Self::DB2_5 => 1250,
+                Self::DB6 => 1750,
+                Self::DB11 => 3100,
+            }
+        }
+
+        pub(crate) fn index(&self) -> usize {
+            *self as usize
+        }
+    }
+
+    impl From<Attenuation> for adc_atten_t {
+        fn from(attenuation: Attenuation) -> Self {
+            match attenuation {
+                Attenuation::DB0 => adc_atten_t_ADC_ATTEN_DB_0,
+                Attenuation::DB2_5 => adc_atten_t_ADC_ATTEN_DB_2_5,
+                Attenuation::DB6 => adc_atten_t_ADC_ATTEN_DB_6,
+                Attenuation::DB11 => adc_atten_t_ADC_ATTEN_DB_11,
+            }
+        }
+    }
+
+    impl core::convert::TryFrom<adc_atten_t> for Attenuation {
+        type Error = EspError;
+
+        #[allow(non_upper_case_globals)]
+        fn try_from(attenuation: adc_atten_t) -> Result<Self, Self::Error> {
+            match attenuation {
+                adc_atten_t_ADC_ATTEN_DB_0 => Ok(Attenuation::DB0),
+                adc_atten_t_ADC_ATTEN_DB_2_5 => Ok(Attenuation::DB2_5),
+                adc_atten_t_ADC_ATTEN_DB_6 => Ok(Attenuation::DB6),
+                adc_atten_t_ADC_ATTEN_DB_11 => Ok(Attenuation::DB11),
+                _ => Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap()),
+            }
+        }
+    }
+
+    /// Number of attenuation levels
+    pub const ATTENUATIONS: usize = 4;
+
+    /// Largest number of samples which can be combined into a single reading
+    pub const MAX_SAMPLES: u16 = 64;
+
+    /// How multiple samples are combined into a single reading
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum SampleFilter {
+        /// Mean of the samples
+        Average,
+        /// Median of the samples, which rejects the occasional spikes of the ADC
+        Median,
+    }
+
+    impl Default for SampleFilter {
+        fn default() -> Self {
+            Self::Average
+        }
+    }
+
+    #[derive(Debug, Copy, Clone)]
     pub struct Config {
         pub resolution: Resolution,
         pub calibration: bool,
+        /// Number of samples taken for each reading, up to [`MAX_SAMPLES`]
+        pub samples: u16,
+        pub filter: SampleFilter,
+        /// User calibration of each attenuation, which takes precedence over
+        /// the eFuse calibration
+        pub user_calibration: [Option<UserCalibration>; ATTENUATIONS],
+        /// How many reads in a row may lose the ADC2 arbitration to the Wi-Fi
+        /// driver with `nb::Error::WouldBlock`, before the next one fails with
+        /// [`super::AdcError::Adc2Busy`]
+        pub adc2_retries: u32,
     }
 
     impl Config {
@@ -128,6 +267,165 @@ pub mod config {
             self.calibration = calibration;
             self
         }
+
+        #[must_use]
+        pub fn oversample(mut self, samples: u16) -> Self {
+            self.samples = samples;
+            self
+        }
+
+        #[must_use]
+        pub fn filter(mut self, filter: SampleFilter) -> Self {
+            self.filter = filter;
+            self
+        }
+
+        #[must_use]
+        pub fn user_calibration(
+            mut self,
+            attenuation: Attenuation,
+            calibration: UserCalibration,
+        ) -> Self {
+            self.user_calibration[attenuation.index()] = Some(calibration);
+            self
+        }
+
+        #[must_use]
+        pub fn adc2_retries(mut self, adc2_retries: u32) -> Self {
+            self.adc2_retries = adc2_retries;
+            self
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                resolution: Default::default(),
+                calibration: false,
+                samples: 1,
+                filter: Default::default(),
+                user_calibration: [None; ATTENUATIONS],
+                adc2_retries: 1000,
+            }
+        }
+    }
+}
+
+/// Error of the ADC reads
+///
+/// Note that this is a breaking change for the `OneShot` implementations of
+/// [`PoweredAdc`], whose error type used to be `EspError`; the driver errors
+/// are now wrapped in [`AdcError::Other`].
+///
+/// ADC2 is shared with the Wi-Fi driver (see [`radio_coexistence`]). On the
+/// ESP32 the Wi-Fi driver locks it for as long as it is started, so reads
+/// fail right away with [`AdcError::Adc2Busy`]. The later chips arbitrate
+/// between the two, and a read which lost the arbitration returns
+/// `nb::Error::WouldBlock` so that it can be retried (e.g. with
+/// `nb::block!`); the samples already taken for that reading are discarded.
+/// After [`config::Config::adc2_retries`] such reads in a row, the next one
+/// fails with [`AdcError::Adc2Busy`], which bounds `nb::block!` while the
+/// Wi-Fi driver keeps winning the arbitration.
+#[cfg(not(feature = "riscv-ulp-hal"))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AdcError {
+    /// ADC2 is locked by the Wi-Fi driver on the ESP32, or lost the
+    /// arbitration to it more than [`config::Config::adc2_retries`] times in
+    /// a row on the other chips
+    Adc2Busy,
+    Other(EspError),
+}
+
+#[cfg(not(feature = "riscv-ulp-hal"))]
+impl From<EspError> for AdcError {
+    fn from(e: EspError) -> Self {
+        AdcError::Other(e)
+    }
+}
+
+#[cfg(not(feature = "riscv-ulp-hal"))]
+impl core::fmt::Display for AdcError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AdcError::Adc2Busy => write!(f, "ADC2 is in use by the Wi-Fi driver"),
+            AdcError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(all(feature = "std", not(feature = "riscv-ulp-hal")))]
+impl std::error::Error for AdcError {}
+
+/// How the ADC unit of a pin is affected by an active Wi-Fi radio
+#[cfg(not(feature = "riscv-ulp-hal"))]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RadioCoexistence {
+    /// The pin can be read at any time
+    Unaffected,
+    /// The Wi-Fi driver has priority on the unit, reads may have to be retried
+    Arbitrated,
+    /// The unit cannot be read while the Wi-Fi driver is started
+    Unavailable,
+}
+
+/// Tells whether a pin can be read while Wi-Fi is active
+///
+/// Only ADC2 is shared with the Wi-Fi driver: on the ESP32 it is locked for
+/// as long as Wi-Fi is started, the later chips arbitrate between the two.
+#[cfg(not(feature = "riscv-ulp-hal"))]
+pub fn radio_coexistence<P: ADCPin>(pin: &P) -> RadioCoexistence {
+    if pin.adc_unit() == adc_unit_t_ADC_UNIT_1 {
+        RadioCoexistence::Unaffected
+    } else if cfg!(esp32) {
+        RadioCoexistence::Unavailable
+    } else {
+        RadioCoexistence::Arbitrated
+    }
+}
+
+/// eFuse calibration scheme used by the ADC
+#[cfg(not(feature = "riscv-ulp-hal"))]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CalibrationScheme {
+    /// Reference voltage burned in eFuse
+    EfuseVref,
+    /// Two-point values burned in eFuse, with line fitting
+    EfuseTwoPoint,
+    /// Two-point values burned in eFuse, with curve fitting
+    EfuseCurveFitting,
+    /// No eFuse values, the driver assumes the default reference voltage
+    DefaultVref,
+}
+
+#[cfg(not(feature = "riscv-ulp-hal"))]
+impl From<CalibrationScheme> for esp_adc_cal_value_t {
+    fn from(scheme: CalibrationScheme) -> Self {
+        match scheme {
+            CalibrationScheme::EfuseVref => esp_adc_cal_value_t_ESP_ADC_CAL_VAL_EFUSE_VREF,
+            CalibrationScheme::EfuseTwoPoint => esp_adc_cal_value_t_ESP_ADC_CAL_VAL_EFUSE_TP,
+            CalibrationScheme::EfuseCurveFitting => {
+                esp_adc_cal_value_t_ESP_ADC_CAL_VAL_EFUSE_TP_FIT
+            }
+            CalibrationScheme::DefaultVref => esp_adc_cal_value_t_ESP_ADC_CAL_VAL_DEFAULT_VREF,
+        }
+    }
+}
+
+#[cfg(not(feature = "riscv-ulp-hal"))]
+impl TryFrom<esp_adc_cal_value_t> for CalibrationScheme {
+    type Error = EspError;
+
+    #[allow(non_upper_case_globals)]
+    fn try_from(scheme: esp_adc_cal_value_t) -> Result<Self, Self::Error> {
+        match scheme {
+            esp_adc_cal_value_t_ESP_ADC_CAL_VAL_EFUSE_VREF => Ok(CalibrationScheme::EfuseVref),
+            esp_adc_cal_value_t_ESP_ADC_CAL_VAL_EFUSE_TP => Ok(CalibrationScheme::EfuseTwoPoint),
+            esp_adc_cal_value_t_ESP_ADC_CAL_VAL_EFUSE_TP_FIT => {
+                Ok(CalibrationScheme::EfuseCurveFitting)
+            }
+            esp_adc_cal_value_t_ESP_ADC_CAL_VAL_DEFAULT_VREF => Ok(CalibrationScheme::DefaultVref),
+            _ => Err(EspError::from(ESP_ERR_NOT_SUPPORTED as i32).unwrap()),
+        }
     }
 }
 
@@ -135,8 +433,14 @@ pub mod config {
 pub struct PoweredAdc<ADC: Adc> {
     adc: ADC,
     resolution: config::Resolution,
-    cal_characteristics:
-        Option<[Option<esp_adc_cal_characteristics_t>; adc_atten_t_ADC_ATTEN_MAX as usize + 1]>,
+    samples: u16,
+    filter: config::SampleFilter,
+    adc2_retries: u32,
+    /// Reads in a row which lost the ADC2 arbitration
+    adc2_lost: u32,
+    calibration_scheme: Option<CalibrationScheme>,
+    user_calibration: [Option<calibration::UserCalibration>; config::ATTENUATIONS],
+    cal_characteristics: [Option<esp_adc_cal_characteristics_t>; config::ATTENUATIONS],
 }
 
 #[cfg(not(feature = "riscv-ulp-hal"))]
@@ -144,27 +448,41 @@ unsafe impl<ADC: Adc> Send for PoweredAdc<ADC> {}
 
 #[cfg(not(feature = "riscv-ulp-hal"))]
 impl<ADC: Adc> PoweredAdc<ADC> {
+    /// Calibration schemes supported by the chip, by order of preference
     #[cfg(esp32)]
-    const CALIBRATION_SCHEME: esp_adc_cal_value_t = esp_adc_cal_value_t_ESP_ADC_CAL_VAL_EFUSE_VREF;
-
-    #[cfg(any(esp32c3, esp32s2))]
-    const CALIBRATION_SCHEME: esp_adc_cal_value_t = esp_adc_cal_value_t_ESP_ADC_CAL_VAL_EFUSE_TP;
+    const CALIBRATION_SCHEMES: &'static [CalibrationScheme] = &[
+        CalibrationScheme::EfuseTwoPoint,
+        CalibrationScheme::EfuseVref,
+    ];
 
-    #[cfg(esp32s3)]
-    const CALIBRATION_SCHEME: esp_adc_cal_value_t =
-        esp_adc_cal_value_t_ESP_ADC_CAL_VAL_EFUSE_TP_FIT;
+    #[cfg(esp32s2)]
+    const CALIBRATION_SCHEMES: &'static [CalibrationScheme] = &[CalibrationScheme::EfuseTwoPoint];
 
-    #[cfg(not(esp32s2))]
-    const MAX_READING: u32 = 4095;
+    #[cfg(esp32c3)]
+    const CALIBRATION_SCHEMES: &'static [CalibrationScheme] = &[
+        CalibrationScheme::EfuseCurveFitting,
+        CalibrationScheme::EfuseTwoPoint,
+    ];
 
-    #[cfg(esp32s2)]
-    const MAX_READING: u32 = 8191;
+    #[cfg(esp32s3)]
+    const CALIBRATION_SCHEMES: &'static [CalibrationScheme] =
+        &[CalibrationScheme::EfuseCurveFitting];
 
     pub fn new(adc: ADC, config: config::Config) -> Result<Self, EspError> {
-        if config.calibration {
-            esp!(unsafe { esp_adc_cal_check_efuse(Self::CALIBRATION_SCHEME) })?;
+        if config.samples == 0 || config.samples > config::MAX_SAMPLES {
+            return Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap());
         }
 
+        let (calibration_scheme, cal_characteristics) = if config.calibration {
+            Self::check_calibration_efuse()?;
+
+            let (scheme, characteristics) = Self::characterize(config.resolution)?;
+
+            (Some(scheme), characteristics)
+        } else {
+            (None, Default::default())
+        };
+
         if ADC::unit() == adc_unit_t_ADC_UNIT_1 {
             esp!(unsafe { adc1_config_width(config.resolution.into()) })?;
         }
@@ -172,92 +490,110 @@ impl<ADC: Adc> PoweredAdc<ADC> {
         Ok(Self {
             adc,
             resolution: config.resolution,
-            cal_characteristics: if config.calibration {
-                Some(Default::default())
-            } else {
-                None
-            },
+            samples: config.samples,
+            filter: config.filter,
+            adc2_retries: config.adc2_retries,
+            adc2_lost: 0,
+            calibration_scheme,
+            user_calibration: config.user_calibration,
+            cal_characteristics,
         })
     }
 
-    pub fn release(self) -> ADC {
-        self.adc
+    /// eFuse calibration scheme in use, if eFuse calibration is enabled
+    ///
+    /// This is the scheme the driver reported when characterizing the ADC,
+    /// which is not necessarily the most preferred one the eFuse has values for.
+    pub fn calibration_scheme(&self) -> Option<CalibrationScheme> {
+        self.calibration_scheme
     }
 
-    fn raw_to_voltage(
+    /// Replaces the user calibration of an attenuation
+    pub fn set_user_calibration(
         &mut self,
-        measurement: c_types::c_int,
-        attenuation: adc_atten_t,
-    ) -> Result<u16, EspError> {
-        let mv = if let Some(cal) = self.get_cal_characteristics(attenuation)? {
-            unsafe { esp_adc_cal_raw_to_voltage(measurement as u32, &cal as *const _) as u16 }
-        } else {
-            (measurement as u32 * Self::get_max_mv(attenuation) / Self::MAX_READING) as u16
-        };
+        attenuation: config::Attenuation,
+        calibration: Option<calibration::UserCalibration>,
+    ) {
+        self.user_calibration[attenuation.index()] = calibration;
+    }
 
-        Ok(mv)
+    /// Fails with `ESP_ERR_NOT_SUPPORTED` if the eFuse has none of the values
+    /// the chip's calibration schemes rely on
+    fn check_calibration_efuse() -> Result<(), EspError> {
+        if Self::CALIBRATION_SCHEMES
+            .iter()
+            .any(|scheme| unsafe { esp_adc_cal_check_efuse((*scheme).into()) } == ESP_OK as i32)
+        {
+            Ok(())
+        } else {
+            Err(EspError::from(ESP_ERR_NOT_SUPPORTED as i32).unwrap())
+        }
     }
 
-    #[allow(non_upper_case_globals)]
-    fn get_max_mv(attenuation: adc_atten_t) -> u32 {
-        #[cfg(esp32)]
-        let mv = match attenuation {
-            adc_atten_t_ADC_ATTEN_DB_0 => 950,
-            adc_atten_t_ADC_ATTEN_DB_2_5 => 1250,
-            adc_atten_t_ADC_ATTEN_DB_6 => 1750,
-            adc_atten_t_ADC_ATTEN_DB_11 => 2450,
-            other => panic!("Unknown attenuation: {}", other),
-        };
+    /// Characterizes the ADC for every attenuation and returns the
+    /// calibration scheme the driver used
+    #[allow(clippy::type_complexity)]
+    fn characterize(
+        resolution: config::Resolution,
+    ) -> Result<
+        (
+            CalibrationScheme,
+            [Option<esp_adc_cal_characteristics_t>; config::ATTENUATIONS],
+        ),
+        EspError,
+    > {
+        let mut characteristics = [None; config::ATTENUATIONS];
+        let mut scheme = CalibrationScheme::DefaultVref;
 
-        #[cfg(any(esp32c3, esp32s2))]
-        let mv = match attenuation {
-            adc_atten_t_ADC_ATTEN_DB_0 => 750,
-            adc_atten_t_ADC_ATTEN_DB_2_5 => 1050,
-            adc_atten_t_ADC_ATTEN_DB_6 => 1300,
-            adc_atten_t_ADC_ATTEN_DB_11 => 2500,
-            other => panic!("Unknown attenuation: {}", other),
-        };
+        for attenuation in Self::ATTENUATIONS {
+            let mut cal: esp_adc_cal_characteristics_t = Default::default();
 
-        #[cfg(esp32s3)]
-        let mv = match attenuation {
-            adc_atten_t_ADC_ATTEN_DB_0 => 950,
-            adc_atten_t_ADC_ATTEN_DB_2_5 => 1250,
-            adc_atten_t_ADC_ATTEN_DB_6 => 1750,
-            adc_atten_t_ADC_ATTEN_DB_11 => 3100,
-            other => panic!("Unknown attenuation: {}", other),
-        };
+            scheme = CalibrationScheme::try_from(unsafe {
+                esp_adc_cal_characterize(
+                    ADC::unit(),
+                    attenuation.into(),
+                    resolution.into(),
+                    0,
+                    &mut cal as *mut _,
+                )
+            })?;
+
+            characteristics[attenuation.index()] = Some(cal);
+        }
+
+        Ok((scheme, characteristics))
+    }
+
+    pub fn release(self) -> ADC {
+        self.adc
+    }
 
-        mv
+    /// Reads the raw conversion result of a pin, without the conversion to millivolts
+    pub fn read_raw<AN, PIN>(&mut self, pin: &mut PIN) -> nb::Result<u16, AdcError>
+    where
+        AN: Analog<ADC>,
+        PIN: embedded_hal::adc::nb::Channel<AN, ID = u8>,
+    {
+        self.read_raw_internal(ADC::unit(), pin.channel() as adc_channel_t)
     }
 
-    fn get_cal_characteristics(
+    fn raw_to_voltage(
         &mut self,
+        measurement: c_types::c_int,
         attenuation: adc_atten_t,
-    ) -> Result<Option<esp_adc_cal_characteristics_t>, EspError> {
-        if let Some(characteristics) = &mut self.cal_characteristics {
-            if let Some(cal) = characteristics[attenuation as usize] {
-                Ok(Some(cal))
-            } else {
-                esp!(unsafe { esp_adc_cal_check_efuse(Self::CALIBRATION_SCHEME) })?;
-
-                let mut cal: esp_adc_cal_characteristics_t = Default::default();
-                unsafe {
-                    esp_adc_cal_characterize(
-                        ADC::unit(),
-                        attenuation,
-                        self.resolution.into(),
-                        0,
-                        &mut cal as *mut _,
-                    )
-                };
-
-                characteristics[attenuation as usize] = Some(cal);
+    ) -> Result<u16, AdcError> {
+        let attenuation = config::Attenuation::try_from(attenuation)?;
+        let raw = measurement.max(0).min(u16::MAX as c_types::c_int) as u16;
 
-                Ok(Some(cal))
-            }
+        let mv = if let Some(calibration) = &self.user_calibration[attenuation.index()] {
+            calibration.raw_to_mv(raw)
+        } else if let Some(cal) = &self.cal_characteristics[attenuation.index()] {
+            unsafe { esp_adc_cal_raw_to_voltage(measurement as u32, cal as *const _) as u16 }
         } else {
-            Ok(None)
-        }
+            calibration::uncalibrated_mv(raw, self.resolution.max_reading(), attenuation.max_mv())
+        };
+
+        Ok(mv)
     }
 
     fn read(
@@ -265,32 +601,235 @@ impl<ADC: Adc> PoweredAdc<ADC> {
         unit: adc_unit_t,
         channel: adc_channel_t,
         atten: adc_atten_t,
-    ) -> nb::Result<u16, EspError> {
-        let mut measurement = 0_i32;
+    ) -> nb::Result<u16, AdcError> {
+        let measurement = self.read_raw_internal(unit, channel)?;
 
-        if unit == adc_unit_t_ADC_UNIT_1 {
-            measurement = unsafe { adc1_get_raw(channel) };
-        } else {
-            let res = unsafe {
-                adc2_get_raw(channel, self.resolution.into(), &mut measurement as *mut _)
+        Ok(self.raw_to_voltage(measurement as c_types::c_int, atten)?)
+    }
+
+    fn read_raw_internal(
+        &mut self,
+        unit: adc_unit_t,
+        channel: adc_channel_t,
+    ) -> nb::Result<u16, AdcError> {
+        let resolution = self.resolution.into();
+
+        let res = self.sample(|| {
+            let mut measurement = 0_i32;
+
+            if unit == adc_unit_t_ADC_UNIT_1 {
+                measurement = unsafe { adc1_get_raw(channel) };
+            } else {
+                let res = unsafe { adc2_get_raw(channel, resolution, &mut measurement as *mut _) };
+
+                // ADC2 is locked by the Wi-Fi driver (or lost the arbitration to it)
+                if res == ESP_ERR_INVALID_STATE as i32 || res == ESP_ERR_TIMEOUT as i32 {
+                    return Err(if cfg!(esp32) {
+                        nb::Error::Other(AdcError::Adc2Busy)
+                    } else {
+                        nb::Error::WouldBlock
+                    });
+                }
+
+                esp!(res).map_err(AdcError::from)?;
             };
 
-            if res == ESP_ERR_INVALID_STATE as i32 {
-                return Err(nb::Error::WouldBlock);
-            } else if res < 0 {
-                return Err(nb::Error::Other(EspError::from(res).unwrap()));
+            Ok(measurement as u16)
+        });
+
+        if let Err(nb::Error::WouldBlock) = res {
+            if self.adc2_lost >= self.adc2_retries {
+                self.adc2_lost = 0;
+
+                return Err(nb::Error::Other(AdcError::Adc2Busy));
             }
-        };
 
-        Ok(self.raw_to_voltage(measurement, atten)?)
+            self.adc2_lost += 1;
+        } else {
+            self.adc2_lost = 0;
+        }
+
+        res
     }
 
     #[cfg(esp32)]
-    fn read_hall(&mut self) -> nb::Result<u16, EspError> {
+    fn read_hall(&mut self) -> nb::Result<u16, AdcError> {
         let measurement = unsafe { hall_sensor_read() };
 
         Ok(self.raw_to_voltage(measurement, adc_atten_t_ADC_ATTEN_DB_0)?)
     }
+
+    /// Takes the configured number of samples and combines them into one reading
+    fn sample(
+        &self,
+        mut read: impl FnMut() -> nb::Result<u16, AdcError>,
+    ) -> nb::Result<u16, AdcError> {
+        let mut samples = [0_u16; config::MAX_SAMPLES as usize];
+        let samples = &mut samples[..self.samples as usize];
+
+        for sample in samples.iter_mut() {
+            *sample = read()?;
+        }
+
+        Ok(combine_samples(samples, self.filter))
+    }
+}
+
+#[cfg(not(feature = "riscv-ulp-hal"))]
+fn combine_samples(samples: &mut [u16], filter: config::SampleFilter) -> u16 {
+    let len = samples.len() as u32;
+
+    match filter {
+        config::SampleFilter::Average => {
+            let sum: u32 = samples.iter().map(|sample| *sample as u32).sum();
+
+            ((sum + len / 2) / len) as u16
+        }
+        config::SampleFilter::Median => {
+            samples.sort_unstable();
+
+            let middle = samples.len() / 2;
+            if samples.len() % 2 == 0 {
+                ((samples[middle - 1] as u32 + samples[middle] as u32 + 1) / 2) as u16
+            } else {
+                samples[middle]
+            }
+        }
+    }
+}
+
+/// ADC channel which owns its pin and whose attenuation can be changed at runtime
+///
+/// Unlike the pins converted with `into_analog_atten_*`, whose attenuation is
+/// part of their type, this allows switching the measurable voltage range on
+/// the fly, see [`PoweredAdc::read_auto_ranging`].
+#[cfg(not(feature = "riscv-ulp-hal"))]
+pub struct AdcChannelDriver<P: ADCPin> {
+    pin: P,
+    attenuation: config::Attenuation,
+}
+
+#[cfg(not(feature = "riscv-ulp-hal"))]
+impl<P: ADCPin> AdcChannelDriver<P> {
+    pub fn new(pin: P, attenuation: config::Attenuation) -> Result<Self, EspError> {
+        esp!(unsafe { gpio_reset_pin(pin.pin()) })?;
+
+        let mut channel = Self { pin, attenuation };
+        channel.set_attenuation(attenuation)?;
+
+        Ok(channel)
+    }
+
+    pub fn release(self) -> P {
+        self.pin
+    }
+
+    pub fn pin(&self) -> &P {
+        &self.pin
+    }
+
+    pub fn attenuation(&self) -> config::Attenuation {
+        self.attenuation
+    }
+
+    pub fn set_attenuation(&mut self, attenuation: config::Attenuation) -> Result<(), EspError> {
+        let channel = self.pin.adc_channel();
+
+        if self.pin.adc_unit() == adc_unit_t_ADC_UNIT_1 {
+            esp!(unsafe { adc1_config_channel_atten(channel, attenuation.into()) })?;
+        } else {
+            esp!(unsafe { adc2_config_channel_atten(channel, attenuation.into()) })?;
+        }
+
+        self.attenuation = attenuation;
+
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "riscv-ulp-hal"))]
+impl<ADC: Adc> PoweredAdc<ADC> {
+    /// Attenuations ordered by increasing voltage range
+    const ATTENUATIONS: [config::Attenuation; 4] = [
+        config::Attenuation::DB0,
+        config::Attenuation::DB2_5,
+        config::Attenuation::DB6,
+        config::Attenuation::DB11,
+    ];
+
+    /// Reads the voltage of a channel, in millivolts
+    pub fn read_channel<P: ADCPin>(
+        &mut self,
+        channel: &mut AdcChannelDriver<P>,
+    ) -> nb::Result<u16, AdcError> {
+        let measurement = self.read_channel_raw(channel)?;
+
+        Ok(self.raw_to_voltage(measurement as c_types::c_int, channel.attenuation.into())?)
+    }
+
+    /// Reads the raw conversion result of a channel
+    pub fn read_channel_raw<P: ADCPin>(
+        &mut self,
+        channel: &mut AdcChannelDriver<P>,
+    ) -> nb::Result<u16, AdcError> {
+        if channel.pin.adc_unit() != ADC::unit() {
+            return Err(nb::Error::Other(
+                EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap().into(),
+            ));
+        }
+
+        self.read_raw_internal(ADC::unit(), channel.pin.adc_channel())
+    }
+
+    /// Reads the voltage of a channel, in millivolts, after switching it to
+    /// the attenuation with the best resolution for the measured voltage
+    ///
+    /// The channel keeps the selected attenuation, so that measuring a slowly
+    /// changing voltage usually takes a single conversion.
+    pub fn read_auto_ranging<P: ADCPin>(
+        &mut self,
+        channel: &mut AdcChannelDriver<P>,
+    ) -> nb::Result<u16, AdcError> {
+        let measurement = self.read_channel_raw(channel)?;
+
+        let mv = if measurement as u32 * 100 >= self.resolution.max_reading() as u32 * 95
+            && channel.attenuation != config::Attenuation::DB11
+        {
+            // Saturated, the voltage can only be known with the widest range
+            channel
+                .set_attenuation(config::Attenuation::DB11)
+                .map_err(AdcError::from)?;
+            self.read_channel(channel)?
+        } else {
+            self.raw_to_voltage(measurement as c_types::c_int, channel.attenuation.into())?
+        };
+
+        let best = Self::ATTENUATIONS
+            .iter()
+            .copied()
+            .find(|attenuation| mv as u32 * 10 < attenuation.max_mv() as u32 * 9)
+            .unwrap_or(config::Attenuation::DB11);
+
+        if best == channel.attenuation {
+            Ok(mv)
+        } else {
+            channel.set_attenuation(best).map_err(AdcError::from)?;
+            self.read_channel(channel)
+        }
+    }
+}
+
+#[cfg(all(esp32, not(feature = "riscv-ulp-hal")))]
+impl PoweredAdc<ADC1> {
+    /// Reads the raw, signed value of the hall sensor
+    ///
+    /// Unlike the ADC pins, the hall sensor is always sampled once.
+    pub fn read_hall_raw(
+        &mut self,
+        _hall_sensor: &mut hall::HallSensor,
+    ) -> nb::Result<i32, AdcError> {
+        Ok(unsafe { hall_sensor_read() })
+    }
 }
 
 #[cfg(not(feature = "riscv-ulp-hal"))]
@@ -300,7 +839,7 @@ where
     AN: Analog<ADC>,
     PIN: embedded_hal_0_2::adc::Channel<AN, ID = u8>,
 {
-    type Error = EspError;
+    type Error = AdcError;
 
     fn read(&mut self, _pin: &mut PIN) -> nb::Result<u16, Self::Error> {
         self.read(
@@ -318,7 +857,7 @@ where
     AN: Analog<ADC>,
     PIN: embedded_hal::adc::nb::Channel<AN, ID = u8>,
 {
-    type Error = EspError;
+    type Error = AdcError;
 
     fn read(&mut self, pin: &mut PIN) -> nb::Result<u16, Self::Error> {
         self.read(
@@ -331,7 +870,7 @@ where
 
 #[cfg(all(esp32, not(feature = "riscv-ulp-hal")))]
 impl embedded_hal_0_2::adc::OneShot<ADC1, u16, hall::HallSensor> for PoweredAdc<ADC1> {
-    type Error = EspError;
+    type Error = AdcError;
 
     fn read(&mut self, _hall_sensor: &mut hall::HallSensor) -> nb::Result<u16, Self::Error> {
         self.read_hall()
@@ -340,13 +879,574 @@ impl embedded_hal_0_2::adc::OneShot<ADC1, u16, hall::HallSensor> for PoweredAdc<
 
 #[cfg(all(esp32, not(feature = "riscv-ulp-hal")))]
 impl embedded_hal::adc::nb::OneShot<ADC1, u16, hall::HallSensor> for PoweredAdc<ADC1> {
-    type Error = EspError;
+    type Error = AdcError;
 
     fn read(&mut self, _hall_sensor: &mut hall::HallSensor) -> nb::Result<u16, Self::Error> {
         self.read_hall()
     }
 }
 
+/// Continuous (DMA) sampling through the ADC digital controller
+///
+/// The controller cycles through a pattern of channels at a fixed sample
+/// rate and stores the conversion results in a ring buffer, from which they
+/// are read frame by frame.
+///
+/// # Example
+///
+/// ```
+/// use esp_idf_hal::adc::{self, config::Attenuation, continuous};
+/// use esp_idf_hal::prelude::*;
+///
+/// let peripherals = Peripherals::take().unwrap();
+///
+/// let config = continuous::Config::new().sample_freq(20.kHz().into());
+/// let channels = [
+///     continuous::Channel::new(&peripherals.pins.gpio32, Attenuation::DB11),
+///     continuous::Channel::new(&peripherals.pins.gpio33, Attenuation::DB0),
+/// ];
+///
+/// let mut adc = continuous::ContinuousAdc::new(peripherals.adc1, &config, &channels).unwrap();
+/// adc.start().unwrap();
+///
+/// let mut frame = [continuous::AdcMeasurement::default(); 128];
+/// let count = adc.read(&mut frame, None).unwrap();
+///
+/// for measurement in &frame[..count] {
+///     println!("channel {}: {}", measurement.channel(), measurement.data());
+/// }
+/// ```
+#[cfg(all(
+    not(feature = "riscv-ulp-hal"),
+    any(esp_idf_version = "4.4", esp_idf_version_major = "5")
+))]
+pub mod continuous {
+    use core::time::Duration;
+
+    use esp_idf_sys::*;
+
+    use crate::gpio::ADCPin;
+    use crate::units::*;
+
+    use super::config::Attenuation;
+    use super::Adc;
+
+    /// Maximum number of entries in the channel pattern
+    #[cfg(not(esp32s3))]
+    pub const MAX_PATTERN_LEN: usize = 16;
+
+    /// Maximum number of entries in the channel pattern
+    #[cfg(esp32s3)]
+    pub const MAX_PATTERN_LEN: usize = 24;
+
+    #[cfg(any(esp32, esp32s2))]
+    const RESULT_BYTES: usize = 2;
+
+    #[cfg(any(esp32c3, esp32s3))]
+    const RESULT_BYTES: usize = 4;
+
+    #[cfg(any(esp32, esp32s2))]
+    const OUTPUT_FORMAT: adc_digi_output_format_t =
+        adc_digi_output_format_t_ADC_DIGI_OUTPUT_FORMAT_TYPE1;
+
+    #[cfg(any(esp32c3, esp32s3))]
+    const OUTPUT_FORMAT: adc_digi_output_format_t =
+        adc_digi_output_format_t_ADC_DIGI_OUTPUT_FORMAT_TYPE2;
+
+    /// The ESP32 and ESP32-S2 controllers need the conversion limit enabled
+    #[cfg(any(esp32, esp32s2))]
+    const CONVERSION_LIMIT: Option<u32> = Some(250);
+
+    #[cfg(any(esp32c3, esp32s3))]
+    const CONVERSION_LIMIT: Option<u32> = None;
+
+    const BIT_WIDTH: u8 = 12;
+
+    #[derive(Debug, Copy, Clone)]
+    pub struct Config {
+        /// Conversions per second, over all channels of the pattern
+        pub sample_freq: Hertz,
+        /// Number of conversion results in a frame, i.e. per DMA interrupt
+        pub frame_measurements: usize,
+        /// Number of frames the driver buffers before dropping results
+        pub frames_count: usize,
+    }
+
+    impl Config {
+        pub fn new() -> Self {
+            Default::default()
+        }
+
+        #[must_use]
+        pub fn sample_freq(mut self, sample_freq: Hertz) -> Self {
+            self.sample_freq = sample_freq;
+            self
+        }
+
+        #[must_use]
+        pub fn frame_measurements(mut self, frame_measurements: usize) -> Self {
+            self.frame_measurements = frame_measurements;
+            self
+        }
+
+        #[must_use]
+        pub fn frames_count(mut self, frames_count: usize) -> Self {
+            self.frames_count = frames_count;
+            self
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                sample_freq: Hertz(20_000),
+                frame_measurements: 100,
+                frames_count: 4,
+            }
+        }
+    }
+
+    /// Entry of the channel pattern
+    #[derive(Debug, Copy, Clone)]
+    pub struct Channel {
+        unit: adc_unit_t,
+        channel: adc_channel_t,
+        attenuation: Attenuation,
+    }
+
+    impl Channel {
+        pub fn new<P: ADCPin>(pin: &P, attenuation: Attenuation) -> Self {
+            Self {
+                unit: pin.adc_unit(),
+                channel: pin.adc_channel(),
+                attenuation,
+            }
+        }
+    }
+
+    /// Conversion result, as written by the DMA
+    #[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct AdcMeasurement([u8; RESULT_BYTES]);
+
+    impl AdcMeasurement {
+        /// Raw conversion result
+        pub fn data(&self) -> u16 {
+            self.raw() as u16 & 0xFFF
+        }
+
+        #[cfg(any(esp32, esp32s2))]
+        pub fn channel(&self) -> adc_channel_t {
+            (self.raw() >> 12) as adc_channel_t & 0xF
+        }
+
+        #[cfg(esp32c3)]
+        pub fn channel(&self) -> adc_channel_t {
+            (self.raw() >> 13) as adc_channel_t & 0x7
+        }
+
+        #[cfg(esp32s3)]
+        pub fn channel(&self) -> adc_channel_t {
+            (self.raw() >> 13) as adc_channel_t & 0xF
+        }
+
+        fn raw(&self) -> u32 {
+            let mut raw = [0; 4];
+            raw[..RESULT_BYTES].copy_from_slice(&self.0);
+
+            u32::from_le_bytes(raw)
+        }
+    }
+
+    pub struct ContinuousAdc<ADC: Adc> {
+        adc: ADC,
+        /// Bit mask of the channels of the pattern
+        channel_mask: u32,
+        running: bool,
+        overflowed: bool,
+    }
+
+    unsafe impl<ADC: Adc> Send for ContinuousAdc<ADC> {}
+
+    impl<ADC: Adc> ContinuousAdc<ADC> {
+        /// Configures the digital controller to sample `channels`, which
+        /// have to belong to `adc`
+        ///
+        /// Fails with `ESP_ERR_INVALID_ARG` if the pattern is empty, too long
+        /// or contains channels of the other unit. On the ESP32 the DMA only
+        /// supports ADC1, so ADC2 fails with `ESP_ERR_NOT_SUPPORTED`.
+        pub fn new(adc: ADC, config: &Config, channels: &[Channel]) -> Result<Self, EspError> {
+            #[cfg(esp32)]
+            if ADC::unit() != adc_unit_t_ADC_UNIT_1 {
+                return Err(EspError::from(ESP_ERR_NOT_SUPPORTED as i32).unwrap());
+            }
+
+            if channels.is_empty()
+                || channels.len() > MAX_PATTERN_LEN
+                || channels.iter().any(|channel| channel.unit != ADC::unit())
+                || config.frame_measurements == 0
+                || config.frames_count == 0
+            {
+                return Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap());
+            }
+
+            let mut pattern: [adc_digi_pattern_config_t; MAX_PATTERN_LEN] = Default::default();
+            let mut channel_mask = 0;
+
+            for (entry, channel) in pattern.iter_mut().zip(channels) {
+                entry.atten = adc_atten_t::from(channel.attenuation) as u8;
+                entry.channel = channel.channel as u8;
+                entry.unit = Self::unit_index();
+                entry.bit_width = BIT_WIDTH;
+
+                channel_mask |= 1 << channel.channel;
+            }
+
+            let frame_size = (config.frame_measurements * RESULT_BYTES) as u32;
+
+            let init_config = adc_digi_init_config_t {
+                max_store_buf_size: frame_size * config.frames_count as u32,
+                conv_num_each_intr: frame_size,
+                adc1_chan_mask: if Self::unit_index() == 0 {
+                    channel_mask
+                } else {
+                    0
+                },
+                adc2_chan_mask: if Self::unit_index() == 1 {
+                    channel_mask
+                } else {
+                    0
+                },
+            };
+
+            esp!(unsafe { adc_digi_initialize(&init_config) })?;
+
+            let digi_config = adc_digi_configuration_t {
+                conv_limit_en: CONVERSION_LIMIT.is_some(),
+                conv_limit_num: CONVERSION_LIMIT.unwrap_or(0),
+                pattern_num: channels.len() as u32,
+                adc_pattern: pattern.as_mut_ptr(),
+                sample_freq_hz: config.sample_freq.0,
+                conv_mode: if Self::unit_index() == 0 {
+                    adc_digi_convert_mode_t_ADC_CONV_SINGLE_UNIT_1
+                } else {
+                    adc_digi_convert_mode_t_ADC_CONV_SINGLE_UNIT_2
+                },
+                format: OUTPUT_FORMAT,
+            };
+
+            if let Err(e) = esp!(unsafe { adc_digi_controller_configure(&digi_config) }) {
+                esp!(unsafe { adc_digi_deinitialize() })?;
+
+                return Err(e);
+            }
+
+            Ok(Self {
+                adc,
+                channel_mask,
+                running: false,
+                overflowed: false,
+            })
+        }
+
+        pub fn release(mut self) -> Result<ADC, EspError> {
+            self.stop()?;
+
+            #[cfg(all(esp_idf_version = "4.4", any(esp32s2, esp32s3, esp32c3)))]
+            self.reset_monitors()?;
+
+            esp!(unsafe { adc_digi_deinitialize() })?;
+
+            Ok(self.adc)
+        }
+
+        pub fn start(&mut self) -> Result<(), EspError> {
+            if !self.running {
+                esp!(unsafe { adc_digi_start() })?;
+                self.running = true;
+            }
+
+            Ok(())
+        }
+
+        pub fn stop(&mut self) -> Result<(), EspError> {
+            if self.running {
+                esp!(unsafe { adc_digi_stop() })?;
+                self.running = false;
+            }
+
+            Ok(())
+        }
+
+        /// Reads the buffered conversion results into `measurements` and
+        /// returns their number
+        ///
+        /// Waits up to `timeout` for at least one frame; `None` waits forever.
+        pub fn read(
+            &mut self,
+            measurements: &mut [AdcMeasurement],
+            timeout: Option<Duration>,
+        ) -> Result<usize, EspError> {
+            let bytes = unsafe {
+                core::slice::from_raw_parts_mut(
+                    measurements.as_mut_ptr() as *mut u8,
+                    measurements.len() * RESULT_BYTES,
+                )
+            };
+
+            Ok(self.read_bytes(bytes, timeout)? / RESULT_BYTES)
+        }
+
+        /// Same as [`Self::read`], but returns the results in the raw format
+        /// of the chip
+        pub fn read_bytes(
+            &mut self,
+            buffer: &mut [u8],
+            timeout: Option<Duration>,
+        ) -> Result<usize, EspError> {
+            let mut len = 0;
+
+            let res = unsafe {
+                adc_digi_read_bytes(
+                    buffer.as_mut_ptr(),
+                    buffer.len() as u32,
+                    &mut len,
+                    // `u32::MAX` is `portMAX_DELAY`, i.e. waits forever
+                    timeout.map_or(u32::MAX, |timeout| {
+                        timeout.as_millis().min((u32::MAX - 1) as u128) as u32
+                    }),
+                )
+            };
+
+            // The results are still valid, but some were dropped because the
+            // buffer was not read fast enough
+            if res == ESP_ERR_INVALID_STATE as i32 {
+                self.overflowed = true;
+            } else {
+                esp!(res)?;
+            }
+
+            Ok(len as usize)
+        }
+
+        /// Whether conversion results were dropped since the last call,
+        /// because they were not read fast enough
+        pub fn overflowed(&mut self) -> bool {
+            core::mem::replace(&mut self.overflowed, false)
+        }
+
+        fn unit_index() -> u8 {
+            if ADC::unit() == adc_unit_t_ADC_UNIT_1 {
+                0
+            } else {
+                1
+            }
+        }
+    }
+
+    /// Hardware threshold monitors of the digital controller
+    ///
+    /// A monitor watches the conversion results of one channel of the
+    /// pattern and raises an interrupt when they cross its threshold, e.g. to
+    /// detect a battery brown-out without reading every frame.
+    ///
+    /// The monitors only work while the digital controller is sampling, i.e.
+    /// while the CPU is awake. Monitoring in deep sleep would need sampling
+    /// driven by the ULP coprocessor, which is out of scope of this module.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use esp_idf_hal::adc::config::Attenuation;
+    /// use esp_idf_hal::adc::continuous::monitor::{Monitor, Threshold};
+    /// use esp_idf_hal::adc::continuous::{self, ContinuousAdc};
+    /// use esp_idf_hal::peripherals::Peripherals;
+    ///
+    /// fn on_brown_out() {
+    ///     // Runs in the ISR: no logging, no blocking
+    /// }
+    ///
+    /// let peripherals = Peripherals::take().unwrap();
+    /// let battery_pin = peripherals.pins.gpio1;
+    ///
+    /// let mut adc = ContinuousAdc::new(
+    ///     peripherals.adc1,
+    ///     &continuous::Config::new(),
+    ///     &[continuous::Channel::new(&battery_pin, Attenuation::DB11)],
+    /// )
+    /// .unwrap();
+    ///
+    /// adc.set_monitor(0, Some(Monitor::new(&battery_pin, Threshold::Below(1800)))).unwrap();
+    /// adc.subscribe_monitors(Some(on_brown_out)).unwrap();
+    /// adc.start().unwrap();
+    ///
+    /// // Later, e.g. from the main loop
+    /// if adc.monitor_events() > 0 {
+    ///     println!("Battery low");
+    /// }
+    /// ```
+    #[cfg(all(esp_idf_version = "4.4", any(esp32s2, esp32s3, esp32c3)))]
+    pub mod monitor {
+        use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+        use esp_idf_sys::*;
+
+        use crate::gpio::ADCPin;
+
+        use super::super::Adc;
+        use super::ContinuousAdc;
+
+        /// Number of hardware threshold monitors
+        pub const MONITORS: usize = 2;
+
+        static SUBSCRIBED: AtomicBool = AtomicBool::new(false);
+        static EVENTS: AtomicU32 = AtomicU32::new(0);
+
+        #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+        pub enum Threshold {
+            /// Triggers when a conversion result is above the value
+            Above(u16),
+            /// Triggers when a conversion result is below the value
+            Below(u16),
+        }
+
+        /// Threshold on the conversion results of one channel
+        #[derive(Debug, Copy, Clone)]
+        pub struct Monitor {
+            unit: adc_unit_t,
+            channel: adc_channel_t,
+            threshold: Threshold,
+        }
+
+        impl Monitor {
+            pub fn new<P: ADCPin>(pin: &P, threshold: Threshold) -> Self {
+                Self {
+                    unit: pin.adc_unit(),
+                    channel: pin.adc_channel(),
+                    threshold,
+                }
+            }
+        }
+
+        impl<ADC: Adc> ContinuousAdc<ADC> {
+            /// Configures monitor `index`, which is below [`MONITORS`], or
+            /// disables it with `None`
+            ///
+            /// Fails with `ESP_ERR_INVALID_ARG` unless the channel of the
+            /// monitor is part of the pattern.
+            pub fn set_monitor(
+                &mut self,
+                index: usize,
+                monitor: Option<Monitor>,
+            ) -> Result<(), EspError> {
+                let index = match index {
+                    0 => adc_digi_monitor_idx_t_ADC_DIGI_MONITOR_IDX0,
+                    1 => adc_digi_monitor_idx_t_ADC_DIGI_MONITOR_IDX1,
+                    _ => return Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap()),
+                };
+
+                if let Some(monitor) = monitor {
+                    if monitor.unit != ADC::unit()
+                        || self.channel_mask & (1 << monitor.channel) == 0
+                    {
+                        return Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap());
+                    }
+
+                    let (mode, threshold) = match monitor.threshold {
+                        Threshold::Above(threshold) => {
+                            (adc_digi_monitor_mode_t_ADC_DIGI_MONITOR_HIGH, threshold)
+                        }
+                        Threshold::Below(threshold) => {
+                            (adc_digi_monitor_mode_t_ADC_DIGI_MONITOR_LOW, threshold)
+                        }
+                    };
+
+                    let mut config = adc_digi_monitor_t {
+                        adc_unit: monitor.unit,
+                        channel: monitor.channel,
+                        mode,
+                        threshold: threshold as u32,
+                    };
+
+                    esp!(unsafe { adc_digi_monitor_set_config(index, &mut config) })?;
+                }
+
+                esp!(unsafe { adc_digi_monitor_enable(index, monitor.is_some()) })
+            }
+
+            /// Enables the monitor interrupt, counting the triggers for
+            /// [`Self::monitor_events`] and calling `callback` on each of them
+            ///
+            /// The callback runs in the ISR, so it must neither block, nor
+            /// allocate, nor log, and may only call the `FromISR` variants of
+            /// the FreeRTOS functions. The interrupt is not allocated in IRAM:
+            /// while the flash cache is disabled (e.g. during flash writes) it
+            /// is deferred, so the callback itself does not need to be placed
+            /// in IRAM either, but a trigger may be reported late.
+            pub fn subscribe_monitors(&mut self, callback: Option<fn()>) -> Result<(), EspError> {
+                self.unsubscribe_monitors()?;
+
+                let arg = callback.map_or(core::ptr::null_mut(), |callback| {
+                    callback as *mut c_types::c_void
+                });
+
+                esp!(unsafe { adc_digi_isr_register(Some(Self::handle_isr), arg, 0) })?;
+                SUBSCRIBED.store(true, Ordering::SeqCst);
+
+                esp!(unsafe {
+                    adc_digi_intr_enable(ADC::unit(), adc_digi_intr_t_ADC_DIGI_INTR_MASK_MONITOR)
+                })
+            }
+
+            pub fn unsubscribe_monitors(&mut self) -> Result<(), EspError> {
+                if SUBSCRIBED.swap(false, Ordering::SeqCst) {
+                    esp!(unsafe {
+                        adc_digi_intr_disable(
+                            ADC::unit(),
+                            adc_digi_intr_t_ADC_DIGI_INTR_MASK_MONITOR,
+                        )
+                    })?;
+                    esp!(unsafe { adc_digi_isr_deregister() })?;
+                }
+
+                Ok(())
+            }
+
+            /// Number of monitor triggers since the last call, while subscribed
+            pub fn monitor_events(&mut self) -> u32 {
+                EVENTS.swap(0, Ordering::SeqCst)
+            }
+
+            pub(super) fn reset_monitors(&mut self) -> Result<(), EspError> {
+                self.unsubscribe_monitors()?;
+
+                for index in 0..MONITORS {
+                    self.set_monitor(index, None)?;
+                }
+
+                Ok(())
+            }
+
+            unsafe extern "C" fn handle_isr(arg: *mut c_types::c_void) {
+                let mask = adc_digi_intr_t_ADC_DIGI_INTR_MASK_MONITOR;
+
+                if adc_digi_intr_get_status(ADC::unit()) & mask as u32 == 0 {
+                    return;
+                }
+
+                adc_digi_intr_clear(ADC::unit(), mask);
+                EVENTS.fetch_add(1, Ordering::SeqCst);
+
+                if !arg.is_null() {
+                    let callback: fn() = core::mem::transmute(arg);
+
+                    callback();
+                }
+            }
+        }
+    }
+}
+
 macro_rules! impl_adc {
     ($adc:ident: $unit:expr) => {
         pub struct $adc(::core::marker::PhantomData<*const ()>);
@@ -0,0 +1,322 @@
+//! Conversion of raw readings to millivolts with user calibration points
+//!
+//! The points typically come from measuring known reference voltages at
+//! production time, and are stored in NVS with [`UserCalibration::to_bytes`].
+
+/// Largest number of points of a multi-point calibration
+pub const MAX_POINTS: usize = 8;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CalibrationError {
+    /// Less than two points
+    TooFewPoints,
+    /// More than [`MAX_POINTS`] points
+    TooManyPoints,
+    /// The raw readings of the points are not strictly increasing
+    UnorderedPoints,
+    /// The serialized calibration is malformed
+    InvalidData,
+}
+
+impl core::fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CalibrationError {}
+
+/// Raw reading measured for a known voltage
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Point {
+    pub raw: u16,
+    pub mv: i32,
+}
+
+impl Point {
+    pub fn new(raw: u16, mv: i32) -> Self {
+        Self { raw, mv }
+    }
+}
+
+/// Piecewise linear calibration through two or more points
+///
+/// Readings outside of the points are extrapolated from the first or last
+/// segment.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct UserCalibration {
+    points: [Point; MAX_POINTS],
+    len: usize,
+}
+
+impl UserCalibration {
+    /// Size of the serialized calibration
+    pub const BYTES: usize = 1 + MAX_POINTS * 6;
+
+    pub fn two_point(low: Point, high: Point) -> Result<Self, CalibrationError> {
+        Self::multi_point(&[low, high])
+    }
+
+    pub fn multi_point(points: &[Point]) -> Result<Self, CalibrationError> {
+        if points.len() < 2 {
+            return Err(CalibrationError::TooFewPoints);
+        }
+
+        if points.len() > MAX_POINTS {
+            return Err(CalibrationError::TooManyPoints);
+        }
+
+        if points.windows(2).any(|pair| pair[0].raw >= pair[1].raw) {
+            return Err(CalibrationError::UnorderedPoints);
+        }
+
+        let mut calibration = Self {
+            points: [Default::default(); MAX_POINTS],
+            len: points.len(),
+        };
+        calibration.points[..points.len()].copy_from_slice(points);
+
+        Ok(calibration)
+    }
+
+    /// Calibration of the form `mv = raw * gain + offset_mv`
+    pub fn gain_offset(
+        gain: f32,
+        offset_mv: f32,
+        max_reading: u16,
+    ) -> Result<Self, CalibrationError> {
+        let round = |mv: f32| {
+            if mv >= 0.0 {
+                (mv + 0.5) as i32
+            } else {
+                (mv - 0.5) as i32
+            }
+        };
+
+        Self::two_point(
+            Point::new(0, round(offset_mv)),
+            Point::new(max_reading, round(max_reading as f32 * gain + offset_mv)),
+        )
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points[..self.len]
+    }
+
+    pub fn raw_to_mv(&self, raw: u16) -> u16 {
+        let points = self.points();
+
+        let segment = points
+            .windows(2)
+            .find(|pair| raw <= pair[1].raw)
+            .unwrap_or(&points[points.len() - 2..]);
+
+        interpolate(segment[0], segment[1], raw)
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::BYTES] {
+        let mut bytes = [0; Self::BYTES];
+        bytes[0] = self.len as u8;
+
+        for (point, chunk) in self.points().iter().zip(bytes[1..].chunks_mut(6)) {
+            chunk[..2].copy_from_slice(&point.raw.to_le_bytes());
+            chunk[2..].copy_from_slice(&point.mv.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CalibrationError> {
+        let len = *bytes.first().ok_or(CalibrationError::InvalidData)? as usize;
+        if len > MAX_POINTS || bytes.len() < 1 + len * 6 {
+            return Err(CalibrationError::InvalidData);
+        }
+
+        let mut points = [Point::default(); MAX_POINTS];
+        for (point, chunk) in points.iter_mut().zip(bytes[1..1 + len * 6].chunks(6)) {
+            point.raw = u16::from_le_bytes([chunk[0], chunk[1]]);
+            point.mv = i32::from_le_bytes([chunk[2], chunk[3], chunk[4], chunk[5]]);
+        }
+
+        Self::multi_point(&points[..len])
+    }
+}
+
+/// Conversion without calibration, assuming that the readings linearly
+/// span `0..=max_mv`
+pub fn uncalibrated_mv(raw: u16, max_reading: u16, max_mv: u16) -> u16 {
+    if max_reading == 0 {
+        return 0;
+    }
+
+    (raw.min(max_reading) as u32 * max_mv as u32 / max_reading as u32) as u16
+}
+
+fn interpolate(from: Point, to: Point, raw: u16) -> u16 {
+    let run = to.raw as i64 - from.raw as i64;
+    let rise = to.mv as i64 - from.mv as i64;
+    let offset = (raw as i64 - from.raw as i64) * rise;
+
+    // Round half away from zero
+    let delta = if offset >= 0 {
+        (offset + run / 2) / run
+    } else {
+        (offset - run / 2) / run
+    };
+
+    (from.mv as i64 + delta).max(0).min(u16::MAX as i64) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_point() {
+        let calibration =
+            UserCalibration::two_point(Point::new(100, 150), Point::new(4000, 3100)).unwrap();
+
+        assert_eq!(calibration.raw_to_mv(100), 150);
+        assert_eq!(calibration.raw_to_mv(4000), 3100);
+        assert_eq!(calibration.raw_to_mv(2050), 1625);
+        // Extrapolated beyond both points
+        assert_eq!(calibration.raw_to_mv(0), 74);
+        assert_eq!(calibration.raw_to_mv(4095), 3172);
+    }
+
+    #[test]
+    fn multi_point_segments() {
+        let calibration = UserCalibration::multi_point(&[
+            Point::new(0, 0),
+            Point::new(1000, 900),
+            Point::new(2000, 2000),
+        ])
+        .unwrap();
+
+        assert_eq!(calibration.raw_to_mv(500), 450);
+        assert_eq!(calibration.raw_to_mv(1000), 900);
+        assert_eq!(calibration.raw_to_mv(1500), 1450);
+        // Extrapolated from the last segment
+        assert_eq!(calibration.raw_to_mv(3000), 3100);
+    }
+
+    #[test]
+    fn interpolation_rounds_half_away_from_zero() {
+        let rising = UserCalibration::two_point(Point::new(0, 0), Point::new(2, 1)).unwrap();
+
+        assert_eq!(rising.raw_to_mv(1), 1);
+
+        let falling = UserCalibration::two_point(Point::new(0, 3), Point::new(2, 0)).unwrap();
+
+        // 1.5 mV
+        assert_eq!(falling.raw_to_mv(1), 1);
+        assert_eq!(interpolate(Point::new(0, 0), Point::new(4, -2), 1), 0);
+    }
+
+    #[test]
+    fn interpolation_saturates() {
+        let calibration =
+            UserCalibration::two_point(Point::new(100, 0), Point::new(200, 100000)).unwrap();
+
+        assert_eq!(calibration.raw_to_mv(0), 0);
+        assert_eq!(calibration.raw_to_mv(4095), u16::MAX);
+    }
+
+    #[test]
+    fn gain_offset() {
+        let calibration = UserCalibration::gain_offset(0.8, -20.0, 4095).unwrap();
+
+        assert_eq!(
+            calibration.points(),
+            &[Point::new(0, -20), Point::new(4095, 3256)]
+        );
+        assert_eq!(calibration.raw_to_mv(10), 0);
+        assert_eq!(calibration.raw_to_mv(1000), 780);
+    }
+
+    #[test]
+    fn invalid_points() {
+        assert_eq!(
+            UserCalibration::multi_point(&[Point::new(5, 0)]),
+            Err(CalibrationError::TooFewPoints)
+        );
+        assert_eq!(
+            UserCalibration::multi_point(&[Point::default(); MAX_POINTS + 1]),
+            Err(CalibrationError::TooManyPoints)
+        );
+        assert_eq!(
+            UserCalibration::multi_point(&[Point::new(5, 0), Point::new(5, 1)]),
+            Err(CalibrationError::UnorderedPoints)
+        );
+        assert_eq!(
+            UserCalibration::multi_point(&[Point::new(6, 0), Point::new(5, 1)]),
+            Err(CalibrationError::UnorderedPoints)
+        );
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let calibration = UserCalibration::multi_point(&[
+            Point::new(0, -5),
+            Point::new(1000, 900),
+            Point::new(4095, 3300),
+        ])
+        .unwrap();
+
+        let bytes = calibration.to_bytes();
+
+        assert_eq!(bytes.len(), UserCalibration::BYTES);
+        assert_eq!(
+            bytes[..13],
+            [3, 0, 0, 0xFB, 0xFF, 0xFF, 0xFF, 0xE8, 0x03, 0x84, 0x03, 0x00, 0x00]
+        );
+        // Unused points are zeroed
+        assert!(bytes[19..].iter().all(|byte| *byte == 0));
+        assert_eq!(UserCalibration::from_bytes(&bytes), Ok(calibration));
+        // Trailing bytes of unused points are not required
+        assert_eq!(UserCalibration::from_bytes(&bytes[..19]), Ok(calibration));
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        let bytes = UserCalibration::two_point(Point::new(0, 0), Point::new(10, 10))
+            .unwrap()
+            .to_bytes();
+
+        assert_eq!(
+            UserCalibration::from_bytes(&[]),
+            Err(CalibrationError::InvalidData)
+        );
+        assert_eq!(
+            UserCalibration::from_bytes(&bytes[..12]),
+            Err(CalibrationError::InvalidData)
+        );
+
+        let mut too_many = bytes;
+        too_many[0] = MAX_POINTS as u8 + 1;
+        assert_eq!(
+            UserCalibration::from_bytes(&too_many),
+            Err(CalibrationError::InvalidData)
+        );
+
+        // Moves the second point onto the first one
+        let mut unordered = bytes;
+        unordered[7] = 0;
+        assert_eq!(
+            UserCalibration::from_bytes(&unordered),
+            Err(CalibrationError::UnorderedPoints)
+        );
+    }
+
+    #[test]
+    fn uncalibrated() {
+        assert_eq!(uncalibrated_mv(0, 4095, 2450), 0);
+        assert_eq!(uncalibrated_mv(4095, 4095, 2450), 2450);
+        assert_eq!(uncalibrated_mv(2048, 4095, 2450), 1225);
+        assert_eq!(uncalibrated_mv(8191, 8191, 2500), 2500);
+        // Readings above the maximum are clamped
+        assert_eq!(uncalibrated_mv(5000, 4095, 2450), 2450);
+        assert_eq!(uncalibrated_mv(100, 0, 2450), 0);
+    }
+}
@@ -32,180 +32,188 @@
 //! }
 //! ```
 
+use core::convert::TryFrom;
 use core::marker::PhantomData;
+use core::time::Duration;
 
 use esp_idf_sys::*;
 
-use crate::delay::portMAX_DELAY;
+use crate::delay::{portMAX_DELAY, TickType};
 use crate::gpio::*;
 
-crate::embedded_hal_error!(
-    CanError,
-    embedded_hal::can::Error,
-    embedded_hal::can::ErrorKind
-);
+/// Longest time the blocking [`embedded_hal::can::blocking::Can::receive`]
+/// waits on the RX queue before checking whether the controller went bus-off
+pub const RECEIVE_SLICE: Duration = Duration::from_millis(100);
+
+/// CAN error which preserves the original `EspError`
+///
+/// Same as the errors of the other drivers, except that it also tells whether
+/// the operation failed because the controller is bus-off, which
+/// `embedded_hal::can::ErrorKind` has no variant for.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CanError {
+    kind: embedded_hal::can::ErrorKind,
+    cause: EspError,
+    bus_off: bool,
+}
 
-pub mod config {
-    use esp_idf_sys::*;
+impl CanError {
+    pub fn new(kind: embedded_hal::can::ErrorKind, cause: EspError) -> Self {
+        Self {
+            kind,
+            cause,
+            bus_off: false,
+        }
+    }
 
-    /// CAN timing
-    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
-    pub enum Timing {
-        B25K,
-        B50K,
-        B100K,
-        B125K,
-        B250K,
-        B500K,
-        B800K,
-        B1M,
+    pub fn other(cause: EspError) -> Self {
+        Self::new(embedded_hal::can::ErrorKind::Other, cause)
     }
 
-    impl From<Timing> for twai_timing_config_t {
-        fn from(resolution: Timing) -> Self {
-            match resolution {
-                Timing::B25K => twai_timing_config_t {
-                    brp: 128,
-                    tseg_1: 16,
-                    tseg_2: 8,
-                    sjw: 3,
-                    triple_sampling: false,
-                },
-                Timing::B50K => twai_timing_config_t {
-                    brp: 80,
-                    tseg_1: 15,
-                    tseg_2: 4,
-                    sjw: 3,
-                    triple_sampling: false,
-                },
-                Timing::B100K => twai_timing_config_t {
-                    brp: 40,
-                    tseg_1: 15,
-                    tseg_2: 4,
-                    sjw: 3,
-                    triple_sampling: false,
-                },
-                Timing::B125K => twai_timing_config_t {
-                    brp: 32,
-                    tseg_1: 15,
-                    tseg_2: 4,
-                    sjw: 3,
-                    triple_sampling: false,
-                },
-                Timing::B250K => twai_timing_config_t {
-                    brp: 16,
-                    tseg_1: 15,
-                    tseg_2: 4,
-                    sjw: 3,
-                    triple_sampling: false,
-                },
-                Timing::B500K => twai_timing_config_t {
-                    brp: 8,
-                    tseg_1: 15,
-                    tseg_2: 4,
-                    sjw: 3,
-                    triple_sampling: false,
-                },
-                Timing::B800K => twai_timing_config_t {
-                    brp: 4,
-                    tseg_1: 16,
-                    tseg_2: 8,
-                    sjw: 3,
-                    triple_sampling: false,
-                },
-                Timing::B1M => twai_timing_config_t {
-                    brp: 4,
-                    tseg_1: 15,
-                    tseg_2: 4,
-                    sjw: 3,
-                    triple_sampling: false,
-                },
-            }
+    pub fn bus_off(cause: EspError) -> Self {
+        Self {
+            bus_off: true,
+            ..Self::other(cause)
         }
     }
 
-    impl Default for Timing {
-        fn default() -> Self {
-            Self::B500K
-        }
+    pub fn cause(&self) -> EspError {
+        self.cause
     }
 
-    /// Is used to filter out unwanted CAN IDs (messages).
-    ///
-    /// Notice that Espressif TWAI (CAN in rest of the world) acceptance filtering
-    /// works differently than common CAN filtering (for example mask bits are inversed).
-    /// However here those differences are hidden away from the user and common CAN filtering is used.
-    ///
-    /// `mask` is used to determine which bits in the incoming CAN ID are compared with the `filter` value.
-    /// Bits in `mask` mean:
-    /// `0`: do not care - the bit is not used for the comparison
-    /// `1`: must match - the bit of the incoming CAN ID must have the same state as in `filter`
-    ///
-    /// Notice that if `mask` is `0`, all CAN IDs are accepted regardless of `filter` value.
-    ///
-    /// ## Examples
-    ///
-    /// This shows how 11 bit CAN ID `0x3AA` goes through filtering engine and is finally accepted:
-    /// ```
-    /// // incoming id [ 0 1 1 1 0 1 0 1 0 1 0 ]
-    /// // mask        [ 1 0 1 0 0 1 1 1 0 0 0 ]
-    /// //               1 = compare
-    /// //               0 = do not care
-    /// // masked id   [ 0 _ 1 _ _ 1 0 1 _ _ _ ]
-    /// // filter      [ 0 0 1 1 1 1 0 1 0 1 1 ]
-    ///
-    /// // incoming id [ 0 1 1 1 0 1 0 1 0 1 0 ]
-    /// // accepted
-    /// ```
-    ///
-    /// Notice that for example `0x7AA` would not be accepted because its MSB bit is `1`,
-    /// but `filter` only accepts `0` in this bit position and `mask` says that this bit must be compared.
-    ///
-    /// Accept only CAN ID `0x567`
-    /// ```
-    /// let filter = 0x567;
-    /// // every bit must match filter
-    /// let mask   = 0x7FF;
-    /// let f = Filter::Standard { filter, mask };
-    /// ```
-    ///
-    /// Accept CAN IDs `0x560 - 0x56F`
-    /// ```
-    /// let filter = 0x560;
-    /// // do not care about 4 LSB bits
-    /// let mask   = 0x7F0;
-    /// let f = Filter::Standard { filter, mask };
-    /// ```
-    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
-    pub enum Filter {
-        // Filter for 11 bit standard CAN IDs
-        Standard { filter: u16, mask: u16 },
-        // Filter for 29 bit extended CAN IDs
-        Extended { filter: u32, mask: u32 },
+    /// The controller is bus-off and needs to be recovered with
+    /// [`CanBus::initiate_recovery`] before it can be used again
+    pub fn is_bus_off(&self) -> bool {
+        self.bus_off
+    }
+}
+
+impl From<EspError> for CanError {
+    fn from(e: EspError) -> Self {
+        Self::other(e)
+    }
+}
+
+impl embedded_hal::can::Error for CanError {
+    fn kind(&self) -> embedded_hal::can::ErrorKind {
+        self.kind
     }
+}
+
+impl core::fmt::Display for CanError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "CanError {{ kind: {}, cause: {}, bus_off: {} }}",
+            self.kind,
+            self.cause(),
+            self.bus_off
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CanError {}
+
+pub mod config {
+    use esp_idf_sys::*;
 
-    impl Filter {
-        /// Filter that allows all standard CAN IDs.
-        pub fn standard_allow_all() -> Self {
-            Self::Standard { filter: 0, mask: 0 }
+    mod filter;
+    mod timing;
+
+    pub use filter::Filter;
+    pub use timing::Timing;
+
+    impl Timing {
+        /// Checks that custom timing parameters are within the ranges
+        /// supported by the TWAI controller
+        ///
+        /// Fails with `ESP_ERR_INVALID_ARG` if [`Self::is_valid`] does not
+        /// hold.
+        pub fn validate(&self) -> Result<(), EspError> {
+            if self.is_valid() {
+                Ok(())
+            } else {
+                Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap())
+            }
         }
+    }
 
-        /// Filter that accepts all extended CAN IDs.
-        pub fn extended_allow_all() -> Self {
-            Self::Extended { filter: 0, mask: 0 }
+    impl From<Timing> for twai_timing_config_t {
+        fn from(timing: Timing) -> Self {
+            match timing.to_custom() {
+                Timing::Custom {
+                    brp,
+                    tseg_1,
+                    tseg_2,
+                    sjw,
+                    triple_sampling,
+                } => twai_timing_config_t {
+                    brp,
+                    tseg_1,
+                    tseg_2,
+                    sjw,
+                    triple_sampling,
+                },
+                _ => unreachable!(),
+            }
         }
     }
 
-    impl Default for Filter {
+    /// Operating mode of the TWAI controller
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub enum Mode {
+        /// Transmits, receives and acknowledges frames
+        Normal,
+        /// Does not require an acknowledgement for transmitted frames, which
+        /// allows a self test (e.g. loopback) without any other node on the bus
+        NoAck,
+        /// Receives frames without ever transmitting or acknowledging anything,
+        /// e.g. for a passive bus monitor
+        ListenOnly,
+    }
+
+    impl Default for Mode {
         fn default() -> Self {
-            Filter::standard_allow_all()
+            Self::Normal
+        }
+    }
+
+    impl From<Mode> for twai_mode_t {
+        fn from(mode: Mode) -> Self {
+            match mode {
+                Mode::Normal => twai_mode_t_TWAI_MODE_NORMAL,
+                Mode::NoAck => twai_mode_t_TWAI_MODE_NO_ACK,
+                Mode::ListenOnly => twai_mode_t_TWAI_MODE_LISTEN_ONLY,
+            }
         }
     }
 
-    #[derive(Debug, Copy, Clone, Default)]
+    #[derive(Debug, Copy, Clone)]
     pub struct Config {
         pub timing: Timing,
         pub filter: Filter,
+        pub alerts: super::Alerts,
+        pub mode: Mode,
+        pub tx_queue_len: u32,
+        pub rx_queue_len: u32,
+        pub clkout_divider: u32,
+        pub intr_flags: u32,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                timing: Default::default(),
+                filter: Default::default(),
+                alerts: Default::default(),
+                mode: Default::default(),
+                tx_queue_len: 5,
+                rx_queue_len: 5,
+                clkout_divider: 0,
+                intr_flags: ESP_INTR_FLAG_LEVEL1,
+            }
+        }
     }
 
     impl Config {
@@ -224,60 +232,411 @@ pub mod config {
             self.filter = filter;
             self
         }
+
+        #[must_use]
+        pub fn mode(mut self, mode: Mode) -> Self {
+            self.mode = mode;
+            self
+        }
+
+        /// Alerts enabled right after the driver is installed
+        #[must_use]
+        pub fn alerts(mut self, alerts: super::Alerts) -> Self {
+            self.alerts = alerts;
+            self
+        }
+
+        /// Number of frames the transmit queue can hold; `0` disables the
+        /// queue, so that only one frame can be pending at a time
+        #[must_use]
+        pub fn tx_queue_len(mut self, len: u32) -> Self {
+            self.tx_queue_len = len;
+            self
+        }
+
+        /// Number of received frames buffered until they are read
+        #[must_use]
+        pub fn rx_queue_len(mut self, len: u32) -> Self {
+            self.rx_queue_len = len;
+            self
+        }
+
+        /// Divider of the APB clock output on the CLKOUT pin; `0` disables
+        /// the output
+        #[must_use]
+        pub fn clkout_divider(mut self, divider: u32) -> Self {
+            self.clkout_divider = divider;
+            self
+        }
+
+        /// `ESP_INTR_FLAG_*` flags used when allocating the driver interrupt
+        #[must_use]
+        pub fn intr_flags(mut self, flags: u32) -> Self {
+            self.intr_flags = flags;
+            self
+        }
+
+        /// Allocate the driver interrupt in IRAM, so that it keeps running
+        /// while the flash cache is disabled
+        #[must_use]
+        pub fn iram_safe(mut self, enable: bool) -> Self {
+            if enable {
+                self.intr_flags |= ESP_INTR_FLAG_IRAM;
+            } else {
+                self.intr_flags &= !ESP_INTR_FLAG_IRAM;
+            }
+            self
+        }
+    }
+}
+
+/// A set of TWAI driver alerts
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Alerts(pub u32);
+
+impl Alerts {
+    pub const NONE: Self = Self(TWAI_ALERT_NONE);
+    pub const ALL: Self = Self(TWAI_ALERT_ALL);
+    /// No more messages queued for transmission
+    pub const TX_IDLE: Self = Self(TWAI_ALERT_TX_IDLE);
+    /// The previous transmission was successful
+    pub const TX_SUCCESS: Self = Self(TWAI_ALERT_TX_SUCCESS);
+    /// Both error counters have dropped below the error warning limit
+    pub const BELOW_ERR_WARN: Self = Self(TWAI_ALERT_BELOW_ERR_WARN);
+    /// The controller has become error active
+    pub const ERR_ACTIVE: Self = Self(TWAI_ALERT_ERR_ACTIVE);
+    /// The controller is undergoing bus recovery
+    pub const RECOVERY_IN_PROGRESS: Self = Self(TWAI_ALERT_RECOVERY_IN_PROGRESS);
+    /// The controller has successfully completed bus recovery
+    pub const BUS_RECOVERED: Self = Self(TWAI_ALERT_BUS_RECOVERED);
+    /// The previous transmission lost arbitration
+    pub const ARB_LOST: Self = Self(TWAI_ALERT_ARB_LOST);
+    /// One of the error counters has exceeded the error warning limit
+    pub const ABOVE_ERR_WARN: Self = Self(TWAI_ALERT_ABOVE_ERR_WARN);
+    /// A bus error has occurred
+    pub const BUS_ERROR: Self = Self(TWAI_ALERT_BUS_ERROR);
+    /// The previous transmission has failed
+    pub const TX_FAILED: Self = Self(TWAI_ALERT_TX_FAILED);
+    /// The RX queue is full, so a received frame was lost
+    pub const RX_QUEUE_FULL: Self = Self(TWAI_ALERT_RX_QUEUE_FULL);
+    /// The controller has become error passive
+    pub const ERR_PASS: Self = Self(TWAI_ALERT_ERR_PASS);
+    /// The controller has gone bus-off
+    pub const BUS_OFF: Self = Self(TWAI_ALERT_BUS_OFF);
+
+    pub fn contains(&self, alerts: Alerts) -> bool {
+        self.0 & alerts.0 == alerts.0
+    }
+
+    pub fn intersects(&self, alerts: Alerts) -> bool {
+        self.0 & alerts.0 != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl core::ops::BitOr for Alerts {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl core::ops::BitOrAssign for Alerts {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl core::ops::BitAnd for Alerts {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// State of the TWAI controller
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum State {
+    /// The controller does not participate in bus activities
+    Stopped,
+    /// The controller can transmit and receive
+    Running,
+    /// The transmit error counter exceeded 255; the controller no longer
+    /// participates in bus activities until it is recovered
+    BusOff,
+    /// The controller is undergoing bus recovery
+    Recovering,
+}
+
+impl TryFrom<twai_state_t> for State {
+    type Error = EspError;
+
+    #[allow(non_upper_case_globals)]
+    fn try_from(state: twai_state_t) -> Result<Self, Self::Error> {
+        match state {
+            twai_state_t_TWAI_STATE_STOPPED => Ok(State::Stopped),
+            twai_state_t_TWAI_STATE_RUNNING => Ok(State::Running),
+            twai_state_t_TWAI_STATE_BUS_OFF => Ok(State::BusOff),
+            twai_state_t_TWAI_STATE_RECOVERING => Ok(State::Recovering),
+            _ => Err(EspError::from(ESP_ERR_NOT_SUPPORTED as i32).unwrap()),
+        }
     }
 }
 
+/// Status and error counters of the TWAI controller
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Status {
+    pub state: State,
+    /// Number of frames queued for transmission or awaiting transmission completion
+    pub msgs_to_tx: u32,
+    /// Number of received frames pending to be read
+    pub msgs_to_rx: u32,
+    /// Transmit error counter (TEC)
+    pub tx_error_counter: u32,
+    /// Receive error counter (REC)
+    pub rx_error_counter: u32,
+    /// Number of failed transmissions
+    pub tx_failed_count: u32,
+    /// Number of frames lost because the RX queue was full
+    pub rx_missed_count: u32,
+    /// Number of times arbitration was lost while transmitting
+    pub arb_lost_count: u32,
+    /// Number of bus errors detected
+    pub bus_error_count: u32,
+}
+
+impl TryFrom<twai_status_info_t> for Status {
+    type Error = EspError;
+
+    fn try_from(info: twai_status_info_t) -> Result<Self, Self::Error> {
+        Ok(Self {
+            state: State::try_from(info.state)?,
+            msgs_to_tx: info.msgs_to_tx,
+            msgs_to_rx: info.msgs_to_rx,
+            tx_error_counter: info.tx_error_counter,
+            rx_error_counter: info.rx_error_counter,
+            tx_failed_count: info.tx_failed_count,
+            rx_missed_count: info.rx_missed_count,
+            arb_lost_count: info.arb_lost_count,
+            bus_error_count: info.bus_error_count,
+        })
+    }
+}
+
+/// Placeholder for an optional CLKOUT or bus-off indicator pin which is not used
+pub struct NoPin;
+
+impl Pin for NoPin {
+    type Error = EspError;
+
+    fn pin(&self) -> i32 {
+        -1
+    }
+}
+
+impl OutputPin for NoPin {}
+
 /// CAN abstraction
-pub struct CanBus<TX: OutputPin, RX: InputPin> {
+pub struct CanBus<TX, RX, CLKOUT = NoPin, BUSOFF = NoPin>
+where
+    TX: OutputPin,
+    RX: InputPin,
+    CLKOUT: OutputPin,
+    BUSOFF: OutputPin,
+{
     can: CAN,
     tx: TX,
     rx: RX,
+    clkout: Option<CLKOUT>,
+    bus_off: Option<BUSOFF>,
 }
 
-unsafe impl<TX: OutputPin, RX: InputPin> Send for CanBus<TX, RX> {}
+unsafe impl<TX, RX, CLKOUT, BUSOFF> Send for CanBus<TX, RX, CLKOUT, BUSOFF>
+where
+    TX: OutputPin,
+    RX: InputPin,
+    CLKOUT: OutputPin,
+    BUSOFF: OutputPin,
+{
+}
 
 impl<TX: OutputPin, RX: InputPin> CanBus<TX, RX> {
     pub fn new(can: CAN, tx: TX, rx: RX, config: config::Config) -> Result<Self, EspError> {
+        Self::new_with_indicators(can, tx, rx, None, None, config)
+    }
+
+    /// Uninstalls the driver; see [`CanBus::release_with_indicators`] for the
+    /// states in which this is possible
+    pub fn release(self) -> Result<(CAN, TX, RX), EspError> {
+        let (can, tx, rx, _, _) = self.release_with_indicators()?;
+
+        Ok((can, tx, rx))
+    }
+}
+
+impl<TX, RX, CLKOUT, BUSOFF> CanBus<TX, RX, CLKOUT, BUSOFF>
+where
+    TX: OutputPin,
+    RX: InputPin,
+    CLKOUT: OutputPin,
+    BUSOFF: OutputPin,
+{
+    /// Creates the driver with optional indicator pins
+    ///
+    /// `clkout` outputs the APB clock divided by [`config::Config::clkout_divider`],
+    /// `bus_off` is driven low while the controller is bus-off.
+    pub fn new_with_indicators(
+        can: CAN,
+        tx: TX,
+        rx: RX,
+        clkout: Option<CLKOUT>,
+        bus_off: Option<BUSOFF>,
+        config: config::Config,
+    ) -> Result<Self, EspError> {
         let general_config = twai_general_config_t {
-            mode: twai_mode_t_TWAI_MODE_NORMAL,
+            mode: config.mode.into(),
             tx_io: tx.pin(),
             rx_io: rx.pin(),
-            clkout_io: -1,
-            bus_off_io: -1,
-            tx_queue_len: 5,
-            rx_queue_len: 5,
-            alerts_enabled: TWAI_ALERT_NONE,
-            clkout_divider: 0,
-            intr_flags: ESP_INTR_FLAG_LEVEL1 as i32,
+            clkout_io: clkout.as_ref().map_or(-1, |pin| pin.pin()),
+            bus_off_io: bus_off.as_ref().map_or(-1, |pin| pin.pin()),
+            tx_queue_len: config.tx_queue_len,
+            rx_queue_len: config.rx_queue_len,
+            alerts_enabled: config.alerts.0,
+            clkout_divider: config.clkout_divider,
+            intr_flags: config.intr_flags as i32,
         };
 
+        config.timing.validate()?;
+
         let timing_config = config.timing.into();
 
         // modify filter and mask to be compatible with TWAI acceptance filter
-        let (filter, mask) = match config.filter {
-            config::Filter::Standard { filter, mask } => {
-                ((filter as u32) << 21, !((mask as u32) << 21))
-            }
-            config::Filter::Extended { filter, mask } => (filter << 3, !(mask << 3)),
-        };
+        let (filter, mask, single_filter) = config.filter.to_acceptance();
 
         let filter_config = twai_filter_config_t {
             acceptance_code: filter,
             acceptance_mask: mask,
-            single_filter: true,
+            single_filter,
         };
 
         esp!(unsafe { twai_driver_install(&general_config, &timing_config, &filter_config) })?;
         esp!(unsafe { twai_start() })?;
 
-        Ok(Self { can, tx, rx })
+        Ok(Self {
+            can,
+            tx,
+            rx,
+            clkout,
+            bus_off,
+        })
     }
 
-    pub fn release(self) -> Result<(CAN, TX, RX), EspError> {
-        esp!(unsafe { twai_stop() })?;
+    /// Uninstalls the driver and returns the peripheral and the pins
+    ///
+    /// The driver can only be uninstalled while the controller is stopped or
+    /// bus-off; a running controller is stopped first. A bus recovery in
+    /// progress cannot be interrupted though, so while the controller is
+    /// [`State::Recovering`] this fails with `ESP_ERR_INVALID_STATE`. Wait for
+    /// [`Alerts::BUS_RECOVERED`] (or for [`CanBus::state`] to report
+    /// [`State::Stopped`]) before releasing a recovering controller.
+    #[allow(clippy::type_complexity)]
+    pub fn release_with_indicators(
+        self,
+    ) -> Result<(CAN, TX, RX, Option<CLKOUT>, Option<BUSOFF>), EspError> {
+        match self.state()? {
+            State::Running => esp!(unsafe { twai_stop() })?,
+            State::Recovering => return Err(EspError::from(ESP_ERR_INVALID_STATE as i32).unwrap()),
+            State::Stopped | State::BusOff => (),
+        }
+
         esp!(unsafe { twai_driver_uninstall() })?;
 
-        Ok((self.can, self.tx, self.rx))
+        Ok((self.can, self.tx, self.rx, self.clkout, self.bus_off))
+    }
+
+    /// Starts the controller, so that it participates in bus activities again
+    ///
+    /// Only valid while the controller is stopped, which is also the state it
+    /// ends up in after a successful bus-off recovery.
+    pub fn start(&mut self) -> Result<(), EspError> {
+        esp!(unsafe { twai_start() })
+    }
+
+    /// Stops the controller; any pending transmissions are cleared
+    pub fn stop(&mut self) -> Result<(), EspError> {
+        esp!(unsafe { twai_stop() })
+    }
+
+    /// Starts the recovery of a bus-off controller
+    ///
+    /// The recovery completes once 128 occurrences of 11 consecutive recessive
+    /// bits have been monitored on the bus, after which the controller is
+    /// stopped and has to be restarted with [`CanBus::start`]. Enable
+    /// [`Alerts::BUS_RECOVERED`] to be notified of the completion.
+    pub fn initiate_recovery(&mut self) -> Result<(), EspError> {
+        esp!(unsafe { twai_initiate_recovery() })
+    }
+
+    pub fn state(&self) -> Result<State, EspError> {
+        Ok(self.status()?.state)
+    }
+
+    /// Replaces the set of enabled alerts, returning the previously enabled ones
+    pub fn configure_alerts(&mut self, alerts: Alerts) -> Result<Alerts, EspError> {
+        let mut previous = 0;
+
+        esp!(unsafe { twai_reconfigure_alerts(alerts.0, &mut previous) })?;
+
+        Ok(Alerts(previous))
+    }
+
+    /// Waits up to `timeout` for any of the enabled alerts to be raised and
+    /// returns (and clears) all raised alerts
+    ///
+    /// Returns `ESP_ERR_TIMEOUT` if no alert was raised in time.
+    pub fn read_alerts(&self, timeout: Option<Duration>) -> Result<Alerts, EspError> {
+        let mut alerts = 0;
+
+        esp!(unsafe { twai_read_alerts(&mut alerts, TickType::from(timeout).0) })?;
+
+        Ok(Alerts(alerts))
+    }
+
+    /// Fails with `ESP_ERR_NOT_SUPPORTED` if the driver reports a state
+    /// unknown to [`State`].
+    pub fn status(&self) -> Result<Status, EspError> {
+        let mut info: twai_status_info_t = Default::default();
+
+        esp!(unsafe { twai_get_status_info(&mut info) })?;
+
+        Status::try_from(info)
+    }
+
+    /// Converts a driver error, telling apart the failures due to the
+    /// controller being bus-off
+    fn to_can_error(&self, e: EspError) -> CanError {
+        if e.code() == ESP_ERR_INVALID_STATE as i32
+            && matches!(self.state(), Ok(State::BusOff) | Ok(State::Recovering))
+        {
+            CanError::bus_off(e)
+        } else {
+            CanError::other(e)
+        }
+    }
+
+    /// Fails with a bus-off error while the controller is bus-off or recovering
+    fn check_on_bus(&self) -> Result<(), CanError> {
+        match self.state()? {
+            State::BusOff | State::Recovering => Err(CanError::bus_off(
+                EspError::from(ESP_ERR_INVALID_STATE as i32).unwrap(),
+            )),
+            _ => Ok(()),
+        }
     }
 
     fn transmit_internal(&mut self, frame: &Frame, delay: TickType_t) -> Result<(), EspError> {
@@ -312,18 +671,38 @@ impl<TX: OutputPin, RX: InputPin> CanBus<TX, RX> {
 //     }
 // }
 
-impl<TX: OutputPin, RX: InputPin> embedded_hal::can::blocking::Can for CanBus<TX, RX> {
+impl<TX, RX, CLKOUT, BUSOFF> embedded_hal::can::blocking::Can for CanBus<TX, RX, CLKOUT, BUSOFF>
+where
+    TX: OutputPin,
+    RX: InputPin,
+    CLKOUT: OutputPin,
+    BUSOFF: OutputPin,
+{
     type Frame = Frame;
     type Error = CanError;
 
     fn transmit(&mut self, frame: &Self::Frame) -> Result<(), Self::Error> {
         self.transmit_internal(frame, portMAX_DELAY)
-            .map_err(CanError::other)
+            .map_err(|e| self.to_can_error(e))
     }
 
+    /// Waits for a frame, returning a bus-off error once the controller
+    /// leaves the bus
+    ///
+    /// The driver only waits on its RX queue, which never fills while the
+    /// controller is bus-off, so the wait is done in slices of
+    /// [`RECEIVE_SLICE`] with the state being re-checked in between.
     fn receive(&mut self) -> Result<Self::Frame, Self::Error> {
-        self.receive_internal(portMAX_DELAY)
-            .map_err(CanError::other)
+        let slice = TickType::from(RECEIVE_SLICE).0;
+
+        loop {
+            self.check_on_bus()?;
+
+            match self.receive_internal(slice) {
+                Err(e) if e.code() == ESP_ERR_TIMEOUT as i32 => continue,
+                result => return result.map_err(|e| self.to_can_error(e)),
+            }
+        }
     }
 }
 
@@ -350,7 +729,13 @@ impl<TX: OutputPin, RX: InputPin> embedded_hal::can::blocking::Can for CanBus<TX
 //     }
 // }
 
-impl<TX: OutputPin, RX: InputPin> embedded_hal::can::nb::Can for CanBus<TX, RX> {
+impl<TX, RX, CLKOUT, BUSOFF> embedded_hal::can::nb::Can for CanBus<TX, RX, CLKOUT, BUSOFF>
+where
+    TX: OutputPin,
+    RX: InputPin,
+    CLKOUT: OutputPin,
+    BUSOFF: OutputPin,
+{
     type Frame = Frame;
     type Error = CanError;
 
@@ -359,7 +744,7 @@ impl<TX: OutputPin, RX: InputPin> embedded_hal::can::nb::Can for CanBus<TX, RX>
             Ok(_) => Ok(None),
             Err(e) if e.code() == ESP_FAIL => Err(nb::Error::WouldBlock),
             Err(e) if e.code() == ESP_ERR_TIMEOUT as i32 => Err(nb::Error::WouldBlock),
-            Err(e) => Err(nb::Error::Other(CanError::other(e))),
+            Err(e) => Err(nb::Error::Other(self.to_can_error(e))),
         }
     }
 
@@ -367,7 +752,7 @@ impl<TX: OutputPin, RX: InputPin> embedded_hal::can::nb::Can for CanBus<TX, RX>
         match self.receive_internal(0) {
             Ok(frame) => Ok(frame),
             Err(e) if e.code() == ESP_ERR_TIMEOUT as i32 => Err(nb::Error::WouldBlock),
-            Err(e) => Err(nb::Error::Other(CanError::other(e))),
+            Err(e) => Err(nb::Error::Other(self.to_can_error(e))),
         }
     }
 }
@@ -431,6 +816,21 @@ impl Frame {
         }
     }
 
+    /// Requests the frame to also be received by the transmitting node itself
+    ///
+    /// Combined with [`config::Mode::NoAck`] this allows loopback tests on a
+    /// single board.
+    #[must_use]
+    pub fn self_reception(mut self, enable: bool) -> Self {
+        unsafe {
+            self.0
+                .__bindgen_anon_1
+                .__bindgen_anon_1
+                .set_self_(enable as u32)
+        };
+        self
+    }
+
     fn get_extended(&self) -> bool {
         unsafe { self.0.__bindgen_anon_1.__bindgen_anon_1.extd() == 1 }
     }
@@ -0,0 +1,548 @@
+//! TWAI acceptance filters
+
+/// Is used to filter out unwanted CAN IDs (messages).
+///
+/// Notice that Espressif TWAI (CAN in rest of the world) acceptance filtering
+/// works differently than common CAN filtering (for example mask bits are inversed).
+/// However here those differences are hidden away from the user and common CAN filtering is used.
+///
+/// `mask` is used to determine which bits in the incoming CAN ID are compared with the `filter` value.
+/// Bits in `mask` mean:
+/// `0`: do not care - the bit is not used for the comparison
+/// `1`: must match - the bit of the incoming CAN ID must have the same state as in `filter`
+///
+/// Notice that if `mask` is `0`, all CAN IDs are accepted regardless of `filter` value.
+///
+/// ## Examples
+///
+/// This shows how 11 bit CAN ID `0x3AA` goes through filtering engine and is finally accepted:
+/// ```
+/// // incoming id [ 0 1 1 1 0 1 0 1 0 1 0 ]
+/// // mask        [ 1 0 1 0 0 1 1 1 0 0 0 ]
+/// //               1 = compare
+/// //               0 = do not care
+/// // masked id   [ 0 _ 1 _ _ 1 0 1 _ _ _ ]
+/// // filter      [ 0 0 1 1 1 1 0 1 0 1 1 ]
+///
+/// // incoming id [ 0 1 1 1 0 1 0 1 0 1 0 ]
+/// // accepted
+/// ```
+///
+/// Notice that for example `0x7AA` would not be accepted because its MSB bit is `1`,
+/// but `filter` only accepts `0` in this bit position and `mask` says that this bit must be compared.
+///
+/// Accept only CAN ID `0x567`
+/// ```
+/// let filter = 0x567;
+/// // every bit must match filter
+/// let mask   = 0x7FF;
+/// let f = Filter::Standard { filter, mask };
+/// ```
+///
+/// Accept CAN IDs `0x560 - 0x56F`
+/// ```
+/// let filter = 0x560;
+/// // do not care about 4 LSB bits
+/// let mask   = 0x7F0;
+/// let f = Filter::Standard { filter, mask };
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Filter {
+    // Filter for 11 bit standard CAN IDs
+    Standard {
+        filter: u16,
+        mask: u16,
+    },
+    // Filter for 29 bit extended CAN IDs
+    Extended {
+        filter: u32,
+        mask: u32,
+    },
+    // Two filters for 11 bit standard CAN IDs; a frame is accepted if it
+    // matches either of them. The first filter additionally matches the
+    // first data byte against `data` and `data_mask`.
+    DualStandard {
+        filter1: u16,
+        mask1: u16,
+        data: u8,
+        data_mask: u8,
+        filter2: u16,
+        mask2: u16,
+    },
+    // Two filters for 29 bit extended CAN IDs; a frame is accepted if it
+    // matches either of them. Only the 16 most significant ID bits
+    // (ID[28:13]) are compared, the lower bits of `filter` and `mask`
+    // are ignored.
+    DualExtended {
+        filter1: u32,
+        mask1: u32,
+        filter2: u32,
+        mask2: u32,
+    },
+}
+
+impl Filter {
+    const STANDARD_ID_BITS: u32 = 0x7ff;
+    const EXTENDED_ID_BITS: u32 = 0x1fff_ffff;
+    const EXTENDED_PREFIX_SHIFT: u32 = 13;
+
+    /// Filter that allows all standard CAN IDs.
+    pub fn standard_allow_all() -> Self {
+        Self::Standard { filter: 0, mask: 0 }
+    }
+
+    /// Filter that accepts all extended CAN IDs.
+    pub fn extended_allow_all() -> Self {
+        Self::Extended { filter: 0, mask: 0 }
+    }
+
+    /// The tightest single filter accepting all of the given standard CAN IDs
+    ///
+    /// Returns `None` if `ids` is empty.
+    pub fn standard_covering(ids: &[u16]) -> Option<Self> {
+        let (filter, mask) =
+            Self::covering(ids.iter().map(|id| *id as u32), Self::STANDARD_ID_BITS)?;
+
+        Some(Self::Standard {
+            filter: filter as u16,
+            mask: mask as u16,
+        })
+    }
+
+    /// The tightest single filter accepting all of the given extended CAN IDs
+    ///
+    /// Returns `None` if `ids` is empty.
+    pub fn extended_covering(ids: &[u32]) -> Option<Self> {
+        let (filter, mask) = Self::covering(ids.iter().copied(), Self::EXTENDED_ID_BITS)?;
+
+        Some(Self::Extended { filter, mask })
+    }
+
+    /// The tightest dual filter accepting all of the given standard CAN IDs
+    ///
+    /// The IDs are split into the two groups which minimize the number of
+    /// accepted IDs. Returns `None` if `ids` is empty.
+    pub fn dual_standard_covering(ids: &[u16]) -> Option<Self> {
+        let ((filter1, mask1), (filter2, mask2)) = Self::dual_covering(
+            ids.len(),
+            |i| ids[i] as u32 & Self::STANDARD_ID_BITS,
+            Self::STANDARD_ID_BITS,
+        )?;
+
+        Some(Self::DualStandard {
+            filter1: filter1 as u16,
+            mask1: mask1 as u16,
+            data: 0,
+            data_mask: 0,
+            filter2: filter2 as u16,
+            mask2: mask2 as u16,
+        })
+    }
+
+    /// The tightest dual filter accepting all of the given extended CAN IDs
+    ///
+    /// As the dual filter only compares ID[28:13], this covers the
+    /// prefixes of the IDs. Returns `None` if `ids` is empty.
+    pub fn dual_extended_covering(ids: &[u32]) -> Option<Self> {
+        let ((filter1, mask1), (filter2, mask2)) = Self::dual_covering(
+            ids.len(),
+            |i| (ids[i] & Self::EXTENDED_ID_BITS) >> Self::EXTENDED_PREFIX_SHIFT,
+            0xffff,
+        )?;
+
+        Some(Self::DualExtended {
+            filter1: filter1 << Self::EXTENDED_PREFIX_SHIFT,
+            mask1: mask1 << Self::EXTENDED_PREFIX_SHIFT,
+            filter2: filter2 << Self::EXTENDED_PREFIX_SHIFT,
+            mask2: mask2 << Self::EXTENDED_PREFIX_SHIFT,
+        })
+    }
+
+    /// Converts the filter to the TWAI acceptance code, acceptance mask
+    /// and single filter flag
+    ///
+    /// Compared to the common CAN notation used by [`Filter`], TWAI mask
+    /// bits are inverted (`1` means "do not care") and the IDs are placed
+    /// at the bit positions of the acceptance registers.
+    pub fn to_acceptance(&self) -> (u32, u32, bool) {
+        match *self {
+            Filter::Standard { filter, mask } => {
+                ((filter as u32) << 21, !((mask as u32) << 21), true)
+            }
+            Filter::Extended { filter, mask } => (filter << 3, !(mask << 3), true),
+            Filter::DualStandard {
+                filter1,
+                mask1,
+                data,
+                data_mask,
+                filter2,
+                mask2,
+            } => {
+                // Filter 1: ID in [31:21], RTR in [20], data byte 1 in [19:16] and [3:0]
+                // Filter 2: ID in [15:5], RTR in [4]
+                let place = |id1: u16, data: u8, id2: u16| {
+                    ((id1 as u32 & Self::STANDARD_ID_BITS) << 21)
+                        | ((data as u32 >> 4) << 16)
+                        | ((id2 as u32 & Self::STANDARD_ID_BITS) << 5)
+                        | (data as u32 & 0xf)
+                };
+
+                (
+                    place(filter1, data, filter2),
+                    !place(mask1, data_mask, mask2),
+                    false,
+                )
+            }
+            Filter::DualExtended {
+                filter1,
+                mask1,
+                filter2,
+                mask2,
+            } => {
+                // Filter 1: ID[28:13] in [31:16]
+                // Filter 2: ID[28:13] in [15:0]
+                let place = |id1: u32, id2: u32| {
+                    let prefix = |id: u32| {
+                        ((id & Self::EXTENDED_ID_BITS) >> Self::EXTENDED_PREFIX_SHIFT) & 0xffff
+                    };
+
+                    (prefix(id1) << 16) | prefix(id2)
+                };
+
+                (place(filter1, filter2), !place(mask1, mask2), false)
+            }
+        }
+    }
+
+    /// Filter and mask matching exactly the bits all `ids` have in common
+    fn covering(ids: impl Iterator<Item = u32>, id_bits: u32) -> Option<(u32, u32)> {
+        let (all_set, any_set, count) = ids
+            .fold((id_bits, 0, 0), |(all_set, any_set, count), id| {
+                (all_set & id, any_set | id, count + 1)
+            });
+
+        if count == 0 {
+            None
+        } else {
+            let mask = !(all_set ^ any_set) & id_bits;
+
+            Some((all_set & mask, mask))
+        }
+    }
+
+    /// Number of IDs accepted by a filter with the given mask
+    fn accepted(mask: u32, id_bits: u32) -> u64 {
+        1 << (id_bits & !mask).count_ones()
+    }
+
+    /// Splits the IDs into the two groups whose covering filters accept
+    /// the fewest IDs in total
+    #[allow(clippy::type_complexity)]
+    fn dual_covering(
+        len: usize,
+        id: impl Fn(usize) -> u32,
+        id_bits: u32,
+    ) -> Option<((u32, u32), (u32, u32))> {
+        // Exhaustive search is affordable for up to 2^15 partitions
+        const MAX_EXHAUSTIVE: usize = 16;
+
+        let cover = |in_first: &dyn Fn(usize) -> bool| {
+            let first = Self::covering((0..len).filter(|i| in_first(*i)).map(&id), id_bits);
+            let second = Self::covering((0..len).filter(|i| !in_first(*i)).map(&id), id_bits);
+
+            match (first, second) {
+                (Some(first), Some(second)) => (first, second),
+                (Some(first), None) => (first, first),
+                (None, Some(second)) => (second, second),
+                (None, None) => unreachable!(),
+            }
+        };
+
+        let cost = |(first, second): ((u32, u32), (u32, u32))| {
+            Self::accepted(first.1, id_bits) + Self::accepted(second.1, id_bits)
+        };
+
+        if len == 0 {
+            return None;
+        }
+
+        let mut best = cover(&|_| true);
+
+        if len <= MAX_EXHAUSTIVE {
+            // The first ID always goes to the first filter, to halve the search space
+            for partition in 0..(1_u32 << (len - 1)) {
+                let candidate = cover(&|i| i == 0 || partition & (1 << (i - 1)) == 0);
+
+                if cost(candidate) < cost(best) {
+                    best = candidate;
+                }
+            }
+        } else {
+            // Split the IDs by the value of a single bit
+            for bit in 0..32 {
+                if id_bits & (1 << bit) != 0 {
+                    let candidate = cover(&|i| id(i) & (1 << bit) == 0);
+
+                    if cost(candidate) < cost(best) {
+                        best = candidate;
+                    }
+                }
+            }
+        }
+
+        Some(best)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::standard_allow_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_acceptance() {
+        assert_eq!(
+            Filter::Standard {
+                filter: 0x567,
+                mask: 0x7ff
+            }
+            .to_acceptance(),
+            (0xace0_0000, 0x001f_ffff, true)
+        );
+        assert_eq!(
+            Filter::Standard {
+                filter: 0x560,
+                mask: 0x7f0
+            }
+            .to_acceptance(),
+            (0xac00_0000, 0x01ff_ffff, true)
+        );
+        assert_eq!(
+            Filter::standard_allow_all().to_acceptance(),
+            (0, 0xffff_ffff, true)
+        );
+    }
+
+    #[test]
+    fn extended_acceptance() {
+        assert_eq!(
+            Filter::Extended {
+                filter: 0x1234_5678,
+                mask: 0x1fff_ffff
+            }
+            .to_acceptance(),
+            (0x91a2_b3c0, 0x0000_0007, true)
+        );
+        assert_eq!(
+            Filter::extended_allow_all().to_acceptance(),
+            (0, 0xffff_ffff, true)
+        );
+    }
+
+    #[test]
+    fn dual_standard_acceptance() {
+        assert_eq!(
+            Filter::DualStandard {
+                filter1: 0x7ff,
+                mask1: 0x7ff,
+                data: 0xa5,
+                data_mask: 0xff,
+                filter2: 0x123,
+                mask2: 0x7ff,
+            }
+            .to_acceptance(),
+            // The RTR bits of both filters are "do not care"
+            (0xffea_2465, 0x0010_0010, false)
+        );
+        // Only the upper nibble of the data byte is compared
+        assert_eq!(
+            Filter::DualStandard {
+                filter1: 0,
+                mask1: 0,
+                data: 0xa0,
+                data_mask: 0xf0,
+                filter2: 0,
+                mask2: 0,
+            }
+            .to_acceptance(),
+            (0x000a_0000, 0xfff0_ffff, false)
+        );
+        // Bits above the 11 bit ID do not leak into the neighbouring fields
+        assert_eq!(
+            Filter::DualStandard {
+                filter1: 0xffff,
+                mask1: 0,
+                data: 0,
+                data_mask: 0,
+                filter2: 0xffff,
+                mask2: 0,
+            }
+            .to_acceptance()
+            .0,
+            0xffe0_ffe0
+        );
+    }
+
+    #[test]
+    fn dual_extended_acceptance() {
+        assert_eq!(
+            Filter::DualExtended {
+                filter1: 0x1234_5678,
+                mask1: 0x1fff_ffff,
+                filter2: 0x0abc_def0,
+                mask2: 0x1fff_e000,
+            }
+            .to_acceptance(),
+            (0x91a2_55e6, 0, false)
+        );
+        // Only ID[28:13] is compared
+        assert_eq!(
+            Filter::DualExtended {
+                filter1: 0x1fff,
+                mask1: 0x1fff,
+                filter2: 0,
+                mask2: 0x1fff_e000,
+            }
+            .to_acceptance(),
+            (0, 0xffff_0000, false)
+        );
+    }
+
+    /// Whether the filter accepts a data frame with the given ID (and a first
+    /// data byte of `0`), as the TWAI controller would decide
+    fn accepts(filter: &Filter, id: u32) -> bool {
+        match *filter {
+            Filter::Standard { filter, mask } => (id ^ filter as u32) & mask as u32 == 0,
+            Filter::Extended { filter, mask } => (id ^ filter) & mask == 0,
+            Filter::DualStandard {
+                filter1,
+                mask1,
+                filter2,
+                mask2,
+                ..
+            } => {
+                (id ^ filter1 as u32) & mask1 as u32 == 0
+                    || (id ^ filter2 as u32) & mask2 as u32 == 0
+            }
+            Filter::DualExtended {
+                filter1,
+                mask1,
+                filter2,
+                mask2,
+            } => {
+                let prefix = 0x1fff_e000;
+
+                (id ^ filter1) & mask1 & prefix == 0 || (id ^ filter2) & mask2 & prefix == 0
+            }
+        }
+    }
+
+    fn accepted_count(filter: &Filter, ids: impl Iterator<Item = u32>) -> usize {
+        ids.filter(|id| accepts(filter, *id)).count()
+    }
+
+    #[test]
+    fn covering_empty() {
+        assert_eq!(Filter::standard_covering(&[]), None);
+        assert_eq!(Filter::extended_covering(&[]), None);
+        assert_eq!(Filter::dual_standard_covering(&[]), None);
+        assert_eq!(Filter::dual_extended_covering(&[]), None);
+    }
+
+    #[test]
+    fn standard_covering() {
+        assert_eq!(
+            Filter::standard_covering(&[0x567]),
+            Some(Filter::Standard {
+                filter: 0x567,
+                mask: 0x7ff
+            })
+        );
+        assert_eq!(
+            Filter::standard_covering(&[0x560, 0x56f, 0x563]),
+            Some(Filter::Standard {
+                filter: 0x560,
+                mask: 0x7f0
+            })
+        );
+    }
+
+    #[test]
+    fn extended_covering() {
+        assert_eq!(
+            Filter::extended_covering(&[0x1000_0000, 0x1000_0001]),
+            Some(Filter::Extended {
+                filter: 0x1000_0000,
+                mask: 0x1fff_fffe
+            })
+        );
+    }
+
+    #[test]
+    fn dual_standard_covering_splits_groups() {
+        let ids = [0x100, 0x700, 0x101, 0x701];
+        let filter = Filter::dual_standard_covering(&ids).unwrap();
+
+        assert_eq!(
+            filter,
+            Filter::DualStandard {
+                filter1: 0x100,
+                mask1: 0x7fe,
+                data: 0,
+                data_mask: 0,
+                filter2: 0x700,
+                mask2: 0x7fe,
+            }
+        );
+        assert_eq!(accepted_count(&filter, 0..0x800), 4);
+    }
+
+    #[test]
+    fn dual_standard_covering_single_id() {
+        let filter = Filter::dual_standard_covering(&[0x123]).unwrap();
+
+        assert_eq!(accepted_count(&filter, 0..0x800), 1);
+        assert!(accepts(&filter, 0x123));
+    }
+
+    #[test]
+    fn dual_standard_covering_many_ids() {
+        // More IDs than are searched exhaustively
+        let ids = (0..10)
+            .map(|i| 0x080 + i)
+            .chain((0..10).map(|i| 0x600 + i))
+            .collect::<Vec<_>>();
+        let filter = Filter::dual_standard_covering(&ids).unwrap();
+
+        assert!(ids.iter().all(|id| accepts(&filter, *id as u32)));
+        // 0x080..=0x08f and 0x600..=0x60f
+        assert_eq!(accepted_count(&filter, 0..0x800), 32);
+    }
+
+    #[test]
+    fn dual_extended_covering() {
+        let ids = [0x1234_5678, 0x1234_4000, 0x0000_2000];
+        let filter = Filter::dual_extended_covering(&ids).unwrap();
+
+        assert!(ids.iter().all(|id| accepts(&filter, *id)));
+        assert!(!accepts(&filter, 0x0000_6000));
+
+        if let Filter::DualExtended {
+            filter1,
+            mask1,
+            filter2,
+            mask2,
+        } = filter
+        {
+            // The bits which are not compared are left clear
+            assert_eq!((filter1 | mask1 | filter2 | mask2) & 0x1fff, 0);
+        } else {
+            unreachable!();
+        }
+    }
+}
@@ -0,0 +1,335 @@
+//! TWAI bit timing
+
+use crate::units::Hertz;
+
+/// CAN timing
+///
+/// The bit time is made of `1 + tseg_1 + tseg_2` time quanta, each of which
+/// lasts `brp` cycles of the APB clock. The bus is sampled at the end of
+/// `tseg_1`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Timing {
+    B25K,
+    B50K,
+    B100K,
+    B125K,
+    B250K,
+    B500K,
+    B800K,
+    B1M,
+    Custom {
+        brp: u32,
+        tseg_1: u8,
+        tseg_2: u8,
+        sjw: u8,
+        triple_sampling: bool,
+    },
+}
+
+impl Timing {
+    /// Frequency of the APB clock the TWAI controller is driven by
+    pub const SOURCE_CLOCK: Hertz = Hertz(80_000_000);
+
+    /// Largest baud rate prescaler, the one used by [`Self::from_bitrate`]
+    /// and accepted by [`Self::is_valid`]
+    ///
+    /// On the ESP32 this is the limit of all silicon revisions, revision 2
+    /// and later also accept multiples of 4 up to 256.
+    #[cfg(esp32)]
+    pub const MAX_BRP: u32 = 128;
+
+    /// Largest baud rate prescaler, the one used by [`Self::from_bitrate`]
+    /// and accepted by [`Self::is_valid`]
+    #[cfg(esp32s2)]
+    pub const MAX_BRP: u32 = 32768;
+
+    /// Largest baud rate prescaler, the one used by [`Self::from_bitrate`]
+    /// and accepted by [`Self::is_valid`]
+    #[cfg(not(any(esp32, esp32s2)))]
+    pub const MAX_BRP: u32 = 16384;
+
+    #[cfg(esp32)]
+    const MAX_BRP_REV2: u32 = 256;
+
+    /// Computes timing parameters for the given bitrate and sample point
+    /// (in permille of the bit time, e.g. `875` for 87.5%)
+    ///
+    /// Returns `None` if the bitrate cannot be derived exactly from the
+    /// APB clock.
+    pub fn from_bitrate(bitrate: Hertz, sample_point: u16) -> Option<Self> {
+        Self::calculate(Self::SOURCE_CLOCK, bitrate, sample_point, Self::MAX_BRP)
+    }
+
+    /// Computes timing parameters for the given bitrate and sample point
+    /// (in permille of the bit time) from an arbitrary source clock
+    ///
+    /// Among all valid prescalers up to `max_brp`, the one yielding the
+    /// sample point closest to the requested one is chosen; ties are
+    /// resolved in favour of more time quanta per bit. SJW is set to
+    /// `min(3, tseg_2)`, like in the predefined timings.
+    pub fn calculate(
+        source_clock: Hertz,
+        bitrate: Hertz,
+        sample_point: u16,
+        max_brp: u32,
+    ) -> Option<Self> {
+        const MIN_TQ: u32 = 8;
+        const MAX_TQ: u32 = 25;
+        const MAX_TSEG_1: u32 = 16;
+        const MAX_TSEG_2: u32 = 8;
+
+        let source_clock = source_clock.0;
+        let bitrate = bitrate.0;
+        let sample_point = sample_point as u32;
+
+        if bitrate == 0 || sample_point == 0 || sample_point >= 1000 {
+            return None;
+        }
+
+        // (sample point error, brp, tseg_1, tseg_2)
+        let mut best: Option<(u32, u32, u32, u32)> = None;
+
+        for brp in (2..=max_brp).step_by(2) {
+            let divider = brp * bitrate;
+            if divider > source_clock {
+                break;
+            }
+
+            if source_clock % divider != 0 {
+                continue;
+            }
+
+            let tq = source_clock / divider;
+            if !(MIN_TQ..=MAX_TQ).contains(&tq) {
+                continue;
+            }
+
+            // Number of quanta before the sample point, sync segment included
+            let before = ((tq * sample_point + 500) / 1000).clamp(2, tq - 1);
+
+            let tseg_1 = (before - 1).min(MAX_TSEG_1);
+            let tseg_2 = tq - 1 - tseg_1;
+            if tseg_2 == 0 || tseg_2 > MAX_TSEG_2 {
+                continue;
+            }
+
+            let actual = (1 + tseg_1) * 1000 / tq;
+            let error = if actual > sample_point {
+                actual - sample_point
+            } else {
+                sample_point - actual
+            };
+
+            if best
+                .map(|(best_error, ..)| error < best_error)
+                .unwrap_or(true)
+            {
+                best = Some((error, brp, tseg_1, tseg_2));
+            }
+        }
+
+        best.map(|(_, brp, tseg_1, tseg_2)| Timing::Custom {
+            brp,
+            tseg_1: tseg_1 as u8,
+            tseg_2: tseg_2 as u8,
+            sjw: tseg_2.min(3) as u8,
+            triple_sampling: false,
+        })
+    }
+
+    /// Whether custom timing parameters are within the ranges supported by
+    /// the TWAI controller
+    ///
+    /// `tseg_1` has to be within 1..=16, `tseg_2` within 1..=8 and `sjw`
+    /// within 1..=4. `brp` has to be even and within 2..=[`Self::MAX_BRP`];
+    /// on the ESP32, multiples of 4 within 132..=256 are accepted as well,
+    /// which requires silicon revision 2 or later. The predefined timings
+    /// are always valid.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Timing::Custom {
+                brp,
+                tseg_1,
+                tseg_2,
+                sjw,
+                ..
+            } => {
+                Self::is_valid_brp(brp)
+                    && (1..=16).contains(&tseg_1)
+                    && (1..=8).contains(&tseg_2)
+                    && (1..=4).contains(&sjw)
+            }
+            _ => true,
+        }
+    }
+
+    /// The parameters of the timing, as [`Timing::Custom`]
+    ///
+    /// Predefined timings are converted to the parameters they stand for,
+    /// custom timings are returned as they are.
+    pub fn to_custom(self) -> Self {
+        let (brp, tseg_1, tseg_2) = match self {
+            Timing::B25K => (128, 16, 8),
+            Timing::B50K => (80, 15, 4),
+            Timing::B100K => (40, 15, 4),
+            Timing::B125K => (32, 15, 4),
+            Timing::B250K => (16, 15, 4),
+            Timing::B500K => (8, 15, 4),
+            Timing::B800K => (4, 16, 8),
+            Timing::B1M => (4, 15, 4),
+            Timing::Custom { .. } => return self,
+        };
+
+        Timing::Custom {
+            brp,
+            tseg_1,
+            tseg_2,
+            sjw: 3,
+            triple_sampling: false,
+        }
+    }
+
+    #[cfg(esp32)]
+    fn is_valid_brp(brp: u32) -> bool {
+        ((2..=Self::MAX_BRP).contains(&brp) && brp % 2 == 0)
+            || ((Self::MAX_BRP + 4..=Self::MAX_BRP_REV2).contains(&brp) && brp % 4 == 0)
+    }
+
+    #[cfg(not(esp32))]
+    fn is_valid_brp(brp: u32) -> bool {
+        (2..=Self::MAX_BRP).contains(&brp) && brp % 2 == 0
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self::B500K
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom(bitrate: u32, sample_point: u16) -> Option<(u32, u8, u8, u8)> {
+        match Timing::calculate(Timing::SOURCE_CLOCK, Hertz(bitrate), sample_point, 128)? {
+            Timing::Custom {
+                brp,
+                tseg_1,
+                tseg_2,
+                sjw,
+                ..
+            } => Some((brp, tseg_1, tseg_2, sjw)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn calculate_exact_sample_points() {
+        assert_eq!(custom(125_000, 875), Some((40, 13, 2, 2)));
+        assert_eq!(custom(250_000, 875), Some((20, 13, 2, 2)));
+        assert_eq!(custom(500_000, 875), Some((10, 13, 2, 2)));
+        assert_eq!(custom(1_000_000, 875), Some((10, 6, 1, 1)));
+    }
+
+    #[test]
+    fn calculate_matches_predefined_timing() {
+        // Same as Timing::B1M
+        assert_eq!(custom(1_000_000, 800), Some((4, 15, 4, 3)));
+    }
+
+    #[test]
+    fn calculate_respects_max_brp() {
+        // Needs a prescaler of at least 160, which is out of range
+        assert_eq!(custom(20_000, 875), None);
+        assert!(Timing::calculate(Timing::SOURCE_CLOCK, Hertz(20_000), 875, 256).is_some());
+    }
+
+    #[test]
+    fn calculate_rejects_invalid_input() {
+        assert_eq!(custom(0, 875), None);
+        assert_eq!(custom(500_000, 0), None);
+        assert_eq!(custom(500_000, 1000), None);
+        // 80 MHz is not an integer multiple of the bitrate
+        assert_eq!(custom(300_001, 875), None);
+    }
+
+    fn valid(brp: u32, tseg_1: u8, tseg_2: u8, sjw: u8) -> bool {
+        Timing::Custom {
+            brp,
+            tseg_1,
+            tseg_2,
+            sjw,
+            triple_sampling: false,
+        }
+        .is_valid()
+    }
+
+    #[test]
+    fn validate_segments() {
+        assert!(valid(8, 15, 4, 3));
+        assert!(valid(8, 1, 1, 1));
+        assert!(valid(8, 16, 8, 4));
+        assert!(!valid(8, 0, 4, 3));
+        assert!(!valid(8, 17, 4, 3));
+        assert!(!valid(8, 15, 0, 3));
+        assert!(!valid(8, 15, 9, 3));
+        assert!(!valid(8, 15, 4, 0));
+        assert!(!valid(8, 15, 4, 5));
+    }
+
+    #[test]
+    fn validate_brp() {
+        assert!(valid(2, 15, 4, 3));
+        assert!(valid(128, 15, 4, 3));
+        assert!(!valid(0, 15, 4, 3));
+        assert!(!valid(1, 15, 4, 3));
+        assert!(!valid(9, 15, 4, 3));
+    }
+
+    #[cfg(esp32)]
+    #[test]
+    fn validate_brp_esp32() {
+        assert!(!valid(130, 15, 4, 3));
+        assert!(valid(132, 15, 4, 3));
+        assert!(!valid(134, 15, 4, 3));
+        assert!(valid(256, 15, 4, 3));
+        assert!(!valid(260, 15, 4, 3));
+    }
+
+    #[cfg(not(esp32))]
+    #[test]
+    fn validate_brp_other_chips() {
+        assert!(valid(130, 15, 4, 3));
+        assert!(valid(Timing::MAX_BRP, 15, 4, 3));
+        assert!(!valid(Timing::MAX_BRP + 2, 15, 4, 3));
+    }
+
+    #[cfg(not(esp32))]
+    #[test]
+    fn low_bitrate_timing_is_valid() {
+        // 80 MHz / 5 kHz = 16000 = 1000 * 16 quanta
+        let timing = Timing::from_bitrate(Hertz(5_000), 875).unwrap();
+
+        assert!(timing.is_valid());
+    }
+
+    #[test]
+    fn predefined_timings_are_valid() {
+        for timing in [
+            Timing::B25K,
+            Timing::B50K,
+            Timing::B100K,
+            Timing::B125K,
+            Timing::B250K,
+            Timing::B500K,
+            Timing::B800K,
+            Timing::B1M,
+        ] {
+            let custom = timing.to_custom();
+
+            assert!(matches!(custom, Timing::Custom { .. }));
+            assert!(custom.is_valid());
+        }
+    }
+}
@@ -0,0 +1,636 @@
+//! CAN frame log formats.
+//!
+//! Encoding and decoding of timestamped frames in two formats:
+//! - the text format of Linux `candump -L` (also understood by `canplayer`,
+//!   SavvyCAN and most other SocketCAN tools), e.g.
+//!   `(1436509052.249713) can0 123#DEADBEEF`;
+//! - a compact binary record, for loggers writing to SD cards or flash.
+//!
+//! Both work with anything implementing the `embedded-hal` `Frame` trait,
+//! e.g. [`crate::can::Frame`].
+//!
+//! # Binary record layout
+//!
+//! All fields are little endian:
+//!
+//! | Offset | Size | Field                                                        |
+//! |--------|------|--------------------------------------------------------------|
+//! | 0      | 8    | Timestamp, in microseconds                                   |
+//! | 8      | 4    | ID; bit 31 is set for extended and bit 30 for remote frames  |
+//! | 12     | 1    | DLC                                                          |
+//! | 13     | DLC  | Data, absent for remote frames                               |
+//!
+//! # Example
+//!
+//! ```
+//! use core::time::Duration;
+//! use embedded_hal::can::{Frame, StandardId};
+//! use esp_idf_hal::{can, canlog};
+//!
+//! let frame: can::Frame = Frame::new(StandardId::new(0x123).unwrap(), &[0xDE, 0xAD]).unwrap();
+//!
+//! let line = canlog::Candump { timestamp: Duration::from_millis(1500), interface: "can0", frame };
+//! assert_eq!(line.to_string(), "(1.500000) can0 123#DEAD");
+//!
+//! let parsed: canlog::Candump<can::Frame> = canlog::Candump::parse("(1.500000) can0 123#DEAD").unwrap();
+//!
+//! let mut buffer = [0; canlog::MAX_RECORD_LEN];
+//! let len = canlog::encode_record(Duration::from_millis(1500), &frame, &mut buffer).unwrap();
+//! let (record, _): (canlog::Record<can::Frame>, _) = canlog::decode_record(&buffer[..len]).unwrap();
+//! ```
+
+use core::fmt::Write;
+use core::time::Duration;
+
+use embedded_hal::can::{ExtendedId, Frame, Id, StandardId};
+
+/// Size of a binary record without data
+pub const RECORD_HEADER_LEN: usize = 13;
+/// Size of the largest binary record
+pub const MAX_RECORD_LEN: usize = RECORD_HEADER_LEN + 8;
+
+const RECORD_EXTENDED: u32 = 1 << 31;
+const RECORD_REMOTE: u32 = 1 << 30;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// The output buffer cannot hold the record
+    BufferTooSmall,
+    /// The input ends in the middle of a record
+    Truncated,
+    /// The input does not follow the format
+    InvalidFormat,
+    /// The CAN ID is out of range
+    InvalidId,
+    /// The frame cannot be represented, e.g. it has more than 8 bytes
+    InvalidFrame,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// Line of a `candump -L` log
+#[derive(Debug, Copy, Clone)]
+pub struct Candump<'a, F> {
+    pub timestamp: Duration,
+    pub interface: &'a str,
+    pub frame: F,
+}
+
+impl<'a, F: Frame> Candump<'a, F> {
+    /// Parses a line, with or without the trailing newline
+    pub fn parse(line: &'a str) -> Result<Self, Error> {
+        let mut fields = line.trim_end().split(' ').filter(|field| !field.is_empty());
+
+        let timestamp = fields.next().ok_or(Error::InvalidFormat)?;
+        let interface = fields.next().ok_or(Error::InvalidFormat)?;
+        let frame = fields.next().ok_or(Error::InvalidFormat)?;
+
+        if fields.next().is_some() {
+            return Err(Error::InvalidFormat);
+        }
+
+        if !timestamp.starts_with('(') || !timestamp.ends_with(')') {
+            return Err(Error::InvalidFormat);
+        }
+
+        Ok(Self {
+            timestamp: parse_timestamp(&timestamp[1..timestamp.len() - 1])?,
+            interface,
+            frame: parse_frame(frame)?,
+        })
+    }
+}
+
+impl<'a, F: Frame> core::fmt::Display for Candump<'a, F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "({}.{:06}) {} ",
+            self.timestamp.as_secs(),
+            self.timestamp.subsec_micros(),
+            self.interface
+        )?;
+
+        match self.frame.id() {
+            Id::Standard(id) => write!(f, "{:03X}#", id.as_raw())?,
+            Id::Extended(id) => write!(f, "{:08X}#", id.as_raw())?,
+        }
+
+        if self.frame.is_remote_frame() {
+            f.write_char('R')?;
+
+            if self.frame.dlc() > 0 {
+                write!(f, "{}", self.frame.dlc())?;
+            }
+        } else {
+            for byte in self.frame.data() {
+                write!(f, "{:02X}", byte)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Result<Duration, Error> {
+    let (secs, fraction) = match timestamp.find('.') {
+        Some(dot) => (&timestamp[..dot], &timestamp[dot + 1..]),
+        None => (timestamp, ""),
+    };
+
+    if secs.is_empty()
+        || fraction.len() > 9
+        || !secs
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(Error::InvalidFormat);
+    }
+
+    let secs: u64 = secs.parse().map_err(|_| Error::InvalidFormat)?;
+
+    let mut nanos = 0;
+    for (index, digit) in fraction.bytes().enumerate() {
+        nanos += (digit - b'0') as u32 * 10u32.pow(8 - index as u32);
+    }
+
+    Ok(Duration::new(secs, nanos))
+}
+
+fn parse_frame<F: Frame>(frame: &str) -> Result<F, Error> {
+    let hash = frame.find('#').ok_or(Error::InvalidFormat)?;
+    let (id, payload) = (&frame[..hash], &frame[hash + 1..]);
+
+    let raw = parse_hex(id)?;
+    let id: Id = match id.len() {
+        3 => standard_id(raw)?,
+        8 => extended_id(raw)?,
+        _ => return Err(Error::InvalidFormat),
+    };
+
+    if let Some(dlc) = payload
+        .strip_prefix('R')
+        .or_else(|| payload.strip_prefix('r'))
+    {
+        let dlc = match dlc {
+            "" => 0,
+            _ => parse_hex(dlc)? as usize,
+        };
+
+        return F::new_remote(id, dlc).ok_or(Error::InvalidFrame);
+    }
+
+    // candump itself never writes them, but `cansend` syntax allows dots
+    // between the data bytes
+    let mut data = [0; 8];
+    let mut len = 0;
+
+    let mut digits = payload.bytes().filter(|c| *c != b'.');
+    while let Some(high) = digits.next() {
+        let low = digits.next().ok_or(Error::InvalidFormat)?;
+
+        if len == data.len() {
+            return Err(Error::InvalidFrame);
+        }
+
+        data[len] = (hex_digit(high)? << 4) | hex_digit(low)?;
+        len += 1;
+    }
+
+    F::new(id, &data[..len]).ok_or(Error::InvalidFrame)
+}
+
+fn standard_id(raw: u32) -> Result<Id, Error> {
+    if raw > 0x7FF {
+        return Err(Error::InvalidId);
+    }
+
+    Ok(StandardId::new(raw as u16).ok_or(Error::InvalidId)?.into())
+}
+
+fn extended_id(raw: u32) -> Result<Id, Error> {
+    Ok(ExtendedId::new(raw).ok_or(Error::InvalidId)?.into())
+}
+
+fn parse_hex(hex: &str) -> Result<u32, Error> {
+    if hex.is_empty() || hex.len() > 8 {
+        return Err(Error::InvalidFormat);
+    }
+
+    hex.bytes()
+        .try_fold(0, |value, c| Ok((value << 4) | hex_digit(c)? as u32))
+}
+
+fn hex_digit(c: u8) -> Result<u8, Error> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(Error::InvalidFormat),
+    }
+}
+
+/// Timestamped frame, as stored in a binary record
+#[derive(Debug, Copy, Clone)]
+pub struct Record<F> {
+    pub timestamp: Duration,
+    pub frame: F,
+}
+
+/// Encodes a frame as a binary record into `buffer` and returns the length
+/// of the record
+pub fn encode_record<F: Frame>(
+    timestamp: Duration,
+    frame: &F,
+    buffer: &mut [u8],
+) -> Result<usize, Error> {
+    let mut id = match frame.id() {
+        Id::Standard(id) => id.as_raw() as u32,
+        Id::Extended(id) => id.as_raw() | RECORD_EXTENDED,
+    };
+
+    let data = if frame.is_remote_frame() {
+        id |= RECORD_REMOTE;
+        &[][..]
+    } else {
+        frame.data()
+    };
+
+    if frame.dlc() > 8 || data.len() > 8 {
+        return Err(Error::InvalidFrame);
+    }
+
+    let len = RECORD_HEADER_LEN + data.len();
+    if buffer.len() < len {
+        return Err(Error::BufferTooSmall);
+    }
+
+    buffer[0..8].copy_from_slice(&(timestamp.as_micros() as u64).to_le_bytes());
+    buffer[8..12].copy_from_slice(&id.to_le_bytes());
+    buffer[12] = frame.dlc() as u8;
+    buffer[RECORD_HEADER_LEN..len].copy_from_slice(data);
+
+    Ok(len)
+}
+
+/// Decodes the binary record at the start of `buffer` and returns it along
+/// with its length, so that consecutive records can be read from a stream
+pub fn decode_record<F: Frame>(buffer: &[u8]) -> Result<(Record<F>, usize), Error> {
+    if buffer.len() < RECORD_HEADER_LEN {
+        return Err(Error::Truncated);
+    }
+
+    let mut timestamp = [0; 8];
+    timestamp.copy_from_slice(&buffer[0..8]);
+
+    let mut raw = [0; 4];
+    raw.copy_from_slice(&buffer[8..12]);
+    let raw = u32::from_le_bytes(raw);
+
+    let dlc = buffer[12] as usize;
+    if dlc > 8 {
+        return Err(Error::InvalidFrame);
+    }
+
+    let id = if raw & RECORD_EXTENDED != 0 {
+        extended_id(raw & !(RECORD_EXTENDED | RECORD_REMOTE))?
+    } else {
+        standard_id(raw & !RECORD_REMOTE)?
+    };
+
+    let (frame, len) = if raw & RECORD_REMOTE != 0 {
+        (F::new_remote(id, dlc), RECORD_HEADER_LEN)
+    } else {
+        let len = RECORD_HEADER_LEN + dlc;
+        if buffer.len() < len {
+            return Err(Error::Truncated);
+        }
+
+        (F::new(id, &buffer[RECORD_HEADER_LEN..len]), len)
+    };
+
+    Ok((
+        Record {
+            timestamp: Duration::from_micros(u64::from_le_bytes(timestamp)),
+            frame: frame.ok_or(Error::InvalidFrame)?,
+        },
+        len,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct TestFrame {
+        id: Id,
+        remote: bool,
+        dlc: usize,
+        data: Vec<u8>,
+    }
+
+    impl Frame for TestFrame {
+        fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+            if data.len() <= 8 {
+                Some(Self {
+                    id: id.into(),
+                    remote: false,
+                    dlc: data.len(),
+                    data: data.to_vec(),
+                })
+            } else {
+                None
+            }
+        }
+
+        fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+            if dlc <= 8 {
+                Some(Self {
+                    id: id.into(),
+                    remote: true,
+                    dlc,
+                    data: Vec::new(),
+                })
+            } else {
+                None
+            }
+        }
+
+        fn is_extended(&self) -> bool {
+            matches!(self.id, Id::Extended(_))
+        }
+
+        fn is_remote_frame(&self) -> bool {
+            self.remote
+        }
+
+        fn id(&self) -> Id {
+            self.id
+        }
+
+        fn dlc(&self) -> usize {
+            self.dlc
+        }
+
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+    }
+
+    fn parse(line: &str) -> Result<Candump<TestFrame>, Error> {
+        Candump::parse(line)
+    }
+
+    fn standard(raw: u16) -> Id {
+        StandardId::new(raw).unwrap().into()
+    }
+
+    fn extended(raw: u32) -> Id {
+        ExtendedId::new(raw).unwrap().into()
+    }
+
+    #[test]
+    fn candump_round_trip() {
+        for line in [
+            "(1436509052.249713) can0 123#DEADBEEF",
+            "(0.000001) vcan1 1FFFFFFF#",
+            "(1.000000) can0 000#0011223344556677",
+            "(5.500000) can0 7FF#R",
+            "(5.500000) can0 00000001#R8",
+        ] {
+            assert_eq!(parse(line).unwrap().to_string(), line);
+        }
+    }
+
+    #[test]
+    fn candump_fields() {
+        let line = parse("(1436509052.249713) can0 123#DEADBEEF\n").unwrap();
+
+        assert_eq!(line.timestamp, Duration::new(1436509052, 249_713_000));
+        assert_eq!(line.interface, "can0");
+        assert_eq!(line.frame.id, standard(0x123));
+        assert!(!line.frame.remote);
+        assert_eq!(line.frame.data, [0xDE, 0xAD, 0xBE, 0xEF]);
+    }
+
+    #[test]
+    fn candump_extended_ids() {
+        assert_eq!(
+            parse("(1.0) can0 1FFFFFFF#01").unwrap().frame.id,
+            extended(0x1FFF_FFFF)
+        );
+        // The ID width, not its value, tells the frame format apart
+        assert_eq!(
+            parse("(1.0) can0 00000123#01").unwrap().frame.id,
+            extended(0x123)
+        );
+        assert_eq!(
+            parse("(1.0) can0 20000000#01").unwrap_err(),
+            Error::InvalidId
+        );
+        assert_eq!(parse("(1.0) can0 800#01").unwrap_err(), Error::InvalidId);
+        assert_eq!(
+            parse("(1.0) can0 0123#01").unwrap_err(),
+            Error::InvalidFormat
+        );
+    }
+
+    #[test]
+    fn candump_remote_frames() {
+        let frame = parse("(1.0) can0 123#R").unwrap().frame;
+
+        assert!(frame.remote);
+        assert_eq!(frame.dlc, 0);
+
+        let frame = parse("(1.0) can0 123#r4").unwrap().frame;
+
+        assert!(frame.remote);
+        assert_eq!(frame.dlc, 4);
+        assert_eq!(parse("(1.0) can0 123#R9").unwrap_err(), Error::InvalidFrame);
+    }
+
+    #[test]
+    fn candump_timestamps() {
+        let line = parse("(1.5) can0 123#").unwrap();
+
+        assert_eq!(line.timestamp, Duration::from_millis(1500));
+        assert_eq!(line.to_string(), "(1.500000) can0 123#");
+        assert_eq!(
+            parse("(7) can0 123#").unwrap().timestamp,
+            Duration::from_secs(7)
+        );
+        assert_eq!(
+            parse("(0.123456789) can0 123#").unwrap().timestamp,
+            Duration::new(0, 123_456_789)
+        );
+
+        for line in [
+            "(0.1234567891) can0 123#",
+            "(.5) can0 123#",
+            "(1.-5) can0 123#",
+            "1.5 can0 123#",
+            "(1.5 can0 123#",
+        ] {
+            assert_eq!(parse(line).unwrap_err(), Error::InvalidFormat, "{}", line);
+        }
+    }
+
+    #[test]
+    fn candump_data_separators() {
+        assert_eq!(
+            parse("(1.5) can0 123#11.22.33").unwrap().frame.data,
+            [0x11, 0x22, 0x33]
+        );
+    }
+
+    #[test]
+    fn candump_truncated_or_invalid() {
+        for line in [
+            "",
+            "(1.0)",
+            "(1.0) can0",
+            "(1.0) can0 123",
+            "(1.0) can0 #11",
+            "(1.0) can0 123#DEA",
+            "(1.0) can0 123#XY",
+            "(1.0) can0 123#11 extra",
+        ] {
+            assert_eq!(parse(line).unwrap_err(), Error::InvalidFormat, "{}", line);
+        }
+
+        assert_eq!(
+            parse("(1.0) can0 123#001122334455667788").unwrap_err(),
+            Error::InvalidFrame
+        );
+    }
+
+    fn encode(timestamp: Duration, frame: &TestFrame) -> Vec<u8> {
+        let mut buffer = [0; MAX_RECORD_LEN];
+        let len = encode_record(timestamp, frame, &mut buffer).unwrap();
+
+        buffer[..len].to_vec()
+    }
+
+    fn decode(record: &[u8]) -> Result<(Record<TestFrame>, usize), Error> {
+        decode_record(record)
+    }
+
+    #[test]
+    fn record_layout() {
+        let frame = TestFrame::new(standard(0x123), &[0xDE, 0xAD]).unwrap();
+
+        assert_eq!(
+            encode(Duration::from_millis(1500), &frame),
+            [
+                0x60, 0xE3, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, // 1500000 us
+                0x23, 0x01, 0x00, 0x00, // ID
+                0x02, // DLC
+                0xDE, 0xAD,
+            ]
+        );
+
+        let frame = TestFrame::new_remote(extended(0x1234_5678), 3).unwrap();
+
+        assert_eq!(
+            encode(Duration::from_micros(1), &frame),
+            [1, 0, 0, 0, 0, 0, 0, 0, 0x78, 0x56, 0x34, 0xD2, 3]
+        );
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let frames = [
+            TestFrame::new(standard(0x000), &[]).unwrap(),
+            TestFrame::new(standard(0x7FF), &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap(),
+            TestFrame::new(extended(0x1FFF_FFFF), &[0xAA]).unwrap(),
+            TestFrame::new_remote(standard(0x123), 0).unwrap(),
+            TestFrame::new_remote(extended(0x0000_0001), 8).unwrap(),
+        ];
+
+        for (index, frame) in frames.iter().enumerate() {
+            let timestamp = Duration::from_micros(1_436_509_052_249_713 + index as u64);
+            let record = encode(timestamp, frame);
+
+            let (decoded, len) = decode(&record).unwrap();
+
+            assert_eq!(len, record.len());
+            assert_eq!(decoded.timestamp, timestamp);
+            assert_eq!(&decoded.frame, frame);
+        }
+    }
+
+    #[test]
+    fn record_stream() {
+        let frames = [
+            TestFrame::new(standard(0x100), &[1, 2, 3]).unwrap(),
+            TestFrame::new_remote(standard(0x200), 2).unwrap(),
+            TestFrame::new(extended(0x300), &[4]).unwrap(),
+        ];
+
+        let stream = frames
+            .iter()
+            .flat_map(|frame| encode(Duration::from_secs(1), frame))
+            .collect::<Vec<_>>();
+
+        let mut offset = 0;
+        for frame in &frames {
+            let (record, len) = decode(&stream[offset..]).unwrap();
+
+            assert_eq!(&record.frame, frame);
+            offset += len;
+        }
+
+        assert_eq!(offset, stream.len());
+    }
+
+    #[test]
+    fn record_truncated() {
+        let frame = TestFrame::new(standard(0x123), &[1, 2, 3]).unwrap();
+        let record = encode(Duration::from_secs(1), &frame);
+
+        for len in 0..record.len() {
+            assert_eq!(
+                decode(&record[..len]).unwrap_err(),
+                Error::Truncated,
+                "{}",
+                len
+            );
+        }
+
+        // Remote frames carry no data
+        let frame = TestFrame::new_remote(standard(0x123), 3).unwrap();
+        let record = encode(Duration::from_secs(1), &frame);
+
+        assert_eq!(record.len(), RECORD_HEADER_LEN);
+        assert!(decode(&record).is_ok());
+    }
+
+    #[test]
+    fn record_invalid() {
+        let frame = TestFrame::new(standard(0x123), &[1, 2, 3]).unwrap();
+
+        let mut buffer = [0; RECORD_HEADER_LEN + 2];
+        assert_eq!(
+            encode_record(Duration::from_secs(1), &frame, &mut buffer),
+            Err(Error::BufferTooSmall)
+        );
+
+        let mut record = encode(Duration::from_secs(1), &frame);
+        record[12] = 9;
+        assert_eq!(decode(&record).unwrap_err(), Error::InvalidFrame);
+
+        // A standard ID out of range
+        let mut record = encode(Duration::from_secs(1), &frame);
+        record[9] = 0x08;
+        assert_eq!(decode(&record).unwrap_err(), Error::InvalidId);
+    }
+}
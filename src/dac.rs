@@ -0,0 +1,725 @@
+//! Digital to analog converter
+//!
+//! The ESP32 and ESP32-S2 have two 8-bit DAC channels, which can output
+//! either a fixed voltage or a cosine wave from the built-in generator.
+//!
+//! # Example
+//!
+//! ```
+//! use esp_idf_hal::dac::{config::CosineConfig, DacDriver};
+//! use esp_idf_hal::prelude::*;
+//!
+//! let peripherals = Peripherals::take().unwrap();
+//!
+//! let mut dac = DacDriver::new(peripherals.pins.gpio25).unwrap();
+//! dac.set_voltage(128).unwrap(); // About VDD3P3_RTC / 2
+//!
+//! dac.start_cosine(&CosineConfig::new().frequency(1.kHz().into())).unwrap();
+//! ```
+//!
+//! Arbitrary waveforms and audio are streamed with `continuous::ContinuousDac`,
+//! on the ESP32, or on the ESP32-S2 with ESP-IDF 5.1 or later.
+
+use esp_idf_sys::*;
+
+use crate::gpio::DACPin;
+
+pub mod config {
+    use esp_idf_sys::*;
+
+    use crate::units::*;
+
+    /// Amplitude of the cosine wave, relative to the full range of the DAC
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum CosineScale {
+        Full,
+        Half,
+        Quarter,
+        Eighth,
+    }
+
+    impl Default for CosineScale {
+        fn default() -> Self {
+            CosineScale::Full
+        }
+    }
+
+    #[cfg(any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0"))]
+    impl From<CosineScale> for dac_cw_scale_t {
+        fn from(scale: CosineScale) -> Self {
+            match scale {
+                CosineScale::Full => dac_cw_scale_t_DAC_CW_SCALE_1,
+                CosineScale::Half => dac_cw_scale_t_DAC_CW_SCALE_2,
+                CosineScale::Quarter => dac_cw_scale_t_DAC_CW_SCALE_4,
+                CosineScale::Eighth => dac_cw_scale_t_DAC_CW_SCALE_8,
+            }
+        }
+    }
+
+    #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+    impl From<CosineScale> for dac_cosine_atten_t {
+        fn from(scale: CosineScale) -> Self {
+            match scale {
+                CosineScale::Full => dac_cosine_atten_t_DAC_COSINE_ATTEN_DB_0,
+                CosineScale::Half => dac_cosine_atten_t_DAC_COSINE_ATTEN_DB_6,
+                CosineScale::Quarter => dac_cosine_atten_t_DAC_COSINE_ATTEN_DB_12,
+                CosineScale::Eighth => dac_cosine_atten_t_DAC_COSINE_ATTEN_DB_18,
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum CosinePhase {
+        Deg0,
+        Deg180,
+    }
+
+    impl Default for CosinePhase {
+        fn default() -> Self {
+            CosinePhase::Deg0
+        }
+    }
+
+    #[cfg(any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0"))]
+    impl From<CosinePhase> for dac_cw_phase_t {
+        fn from(phase: CosinePhase) -> Self {
+            match phase {
+                CosinePhase::Deg0 => dac_cw_phase_t_DAC_CW_PHASE_0,
+                CosinePhase::Deg180 => dac_cw_phase_t_DAC_CW_PHASE_180,
+            }
+        }
+    }
+
+    #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+    impl From<CosinePhase> for dac_cosine_phase_t {
+        fn from(phase: CosinePhase) -> Self {
+            match phase {
+                CosinePhase::Deg0 => dac_cosine_phase_t_DAC_COSINE_PHASE_0,
+                CosinePhase::Deg180 => dac_cosine_phase_t_DAC_COSINE_PHASE_180,
+            }
+        }
+    }
+
+    /// Configuration of the cosine-wave generator
+    #[derive(Debug, Copy, Clone)]
+    pub struct CosineConfig {
+        /// Frequency of the wave, from 130 Hz to 55 kHz
+        pub frequency: Hertz,
+        pub scale: CosineScale,
+        pub phase: CosinePhase,
+        /// DC offset of the wave, in DAC steps
+        pub offset: i8,
+    }
+
+    impl CosineConfig {
+        pub fn new() -> Self {
+            Default::default()
+        }
+
+        #[must_use]
+        pub fn frequency(mut self, frequency: Hertz) -> Self {
+            self.frequency = frequency;
+            self
+        }
+
+        #[must_use]
+        pub fn scale(mut self, scale: CosineScale) -> Self {
+            self.scale = scale;
+            self
+        }
+
+        #[must_use]
+        pub fn phase(mut self, phase: CosinePhase) -> Self {
+            self.phase = phase;
+            self
+        }
+
+        #[must_use]
+        pub fn offset(mut self, offset: i8) -> Self {
+            self.offset = offset;
+            self
+        }
+    }
+
+    impl Default for CosineConfig {
+        fn default() -> Self {
+            Self {
+                frequency: Hertz(1000),
+                scale: Default::default(),
+                phase: Default::default(),
+                offset: 0,
+            }
+        }
+    }
+}
+
+/// DAC channel which owns its pin
+///
+/// With ESP-IDF 5.1 or later, the channel goes through the `dac_oneshot` and
+/// `dac_cosine` drivers: ESP-IDF aborts at boot if the legacy DAC driver is
+/// linked together with `dac_continuous`, which [`continuous::ContinuousDac`]
+/// uses there.
+pub struct DacDriver<P: DACPin> {
+    pin: P,
+    cosine: bool,
+    #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+    oneshot: dac_oneshot_handle_t,
+    #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+    cosine_handle: dac_cosine_handle_t,
+}
+
+#[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+unsafe impl<P: DACPin + Send> Send for DacDriver<P> {}
+
+impl<P: DACPin> DacDriver<P> {
+    pub fn new(pin: P) -> Result<Self, EspError> {
+        let mut dac = Self {
+            pin,
+            cosine: false,
+            #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+            oneshot: core::ptr::null_mut(),
+            #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+            cosine_handle: core::ptr::null_mut(),
+        };
+
+        dac.enable_output()?;
+
+        Ok(dac)
+    }
+
+    pub fn release(mut self) -> Result<P, EspError> {
+        self.stop_cosine()?;
+        self.disable_output()?;
+
+        Ok(self.pin)
+    }
+
+    pub fn pin(&self) -> &P {
+        &self.pin
+    }
+
+    /// Outputs `value` / 256 of VDD3P3_RTC, stopping the cosine wave if any
+    pub fn set_voltage(&mut self, value: u8) -> Result<(), EspError> {
+        self.stop_cosine()?;
+
+        #[cfg(any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0"))]
+        esp!(unsafe { dac_output_voltage(self.pin.dac_channel(), value) })?;
+
+        #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+        esp!(unsafe { dac_oneshot_output_voltage(self.oneshot, value) })?;
+
+        Ok(())
+    }
+
+    /// Outputs a cosine wave from the built-in generator
+    ///
+    /// The generator is shared by both channels: they can output the same
+    /// wave with different scales, phases and offsets, but the frequency of
+    /// the last configured channel applies to both.
+    #[cfg(any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0"))]
+    pub fn start_cosine(&mut self, config: &config::CosineConfig) -> Result<(), EspError> {
+        let mut cw_config = dac_cw_config_t {
+            en_ch: self.pin.dac_channel(),
+            scale: config.scale.into(),
+            phase: config.phase.into(),
+            freq: config.frequency.0,
+            offset: config.offset,
+        };
+
+        esp!(unsafe { dac_cw_generator_config(&mut cw_config) })?;
+        esp!(unsafe { dac_cw_generator_enable() })?;
+
+        self.cosine = true;
+
+        Ok(())
+    }
+
+    /// Outputs a cosine wave from the built-in generator
+    ///
+    /// The generator is shared by both channels: they can output the same
+    /// wave with different scales, phases and offsets, but the frequency of
+    /// the last configured channel applies to both.
+    #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+    pub fn start_cosine(&mut self, config: &config::CosineConfig) -> Result<(), EspError> {
+        // A channel belongs to one driver at a time
+        self.stop_cosine()?;
+        self.disable_output()?;
+
+        let mut cosine_config = dac_cosine_config_t {
+            chan_id: self.pin.dac_channel(),
+            freq_hz: config.frequency.0,
+            clk_src: soc_periph_dac_cosine_clk_src_t_DAC_COSINE_CLK_SRC_DEFAULT,
+            atten: config.scale.into(),
+            phase: config.phase.into(),
+            offset: config.offset,
+            ..Default::default()
+        };
+        cosine_config.flags.set_force_set_freq(1);
+
+        let res = esp!(unsafe { dac_cosine_new_channel(&cosine_config, &mut self.cosine_handle) })
+            .and_then(|_| {
+                esp!(unsafe { dac_cosine_start(self.cosine_handle) }).or_else(|e| {
+                    esp!(unsafe { dac_cosine_del_channel(self.cosine_handle) })?;
+
+                    Err(e)
+                })
+            });
+
+        if let Err(e) = res {
+            self.cosine_handle = core::ptr::null_mut();
+            self.enable_output()?;
+
+            return Err(e);
+        }
+
+        self.cosine = true;
+
+        Ok(())
+    }
+
+    /// Stops the cosine-wave generator
+    ///
+    /// With ESP-IDF before 5.1, as the generator is shared, this also stops
+    /// the wave of the other channel.
+    pub fn stop_cosine(&mut self) -> Result<(), EspError> {
+        if self.cosine {
+            #[cfg(any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0"))]
+            esp!(unsafe { dac_cw_generator_disable() })?;
+
+            #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+            {
+                esp!(unsafe { dac_cosine_stop(self.cosine_handle) })?;
+                esp!(unsafe { dac_cosine_del_channel(self.cosine_handle) })?;
+
+                self.cosine_handle = core::ptr::null_mut();
+            }
+
+            self.cosine = false;
+
+            #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+            self.enable_output()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_cosine(&self) -> bool {
+        self.cosine
+    }
+
+    #[cfg(any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0"))]
+    fn enable_output(&mut self) -> Result<(), EspError> {
+        esp!(unsafe { dac_output_enable(self.pin.dac_channel()) })
+    }
+
+    #[cfg(any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0"))]
+    fn disable_output(&mut self) -> Result<(), EspError> {
+        esp!(unsafe { dac_output_disable(self.pin.dac_channel()) })
+    }
+
+    #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+    fn enable_output(&mut self) -> Result<(), EspError> {
+        let oneshot_config = dac_oneshot_config_t {
+            chan_id: self.pin.dac_channel(),
+        };
+
+        esp!(unsafe { dac_oneshot_new_channel(&oneshot_config, &mut self.oneshot) })
+    }
+
+    #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+    fn disable_output(&mut self) -> Result<(), EspError> {
+        if !self.oneshot.is_null() {
+            esp!(unsafe { dac_oneshot_del_channel(self.oneshot) })?;
+
+            self.oneshot = core::ptr::null_mut();
+        }
+
+        Ok(())
+    }
+}
+
+/// Continuous output of a sample stream, fed by DMA
+///
+/// With ESP-IDF 5.1 or later, the samples go through the `dac_continuous`
+/// driver, which supports both the ESP32 and the ESP32-S2. With earlier
+/// versions, only the ESP32 is supported, through the built-in DAC mode of
+/// I2S0.
+///
+/// The driver cycles through `buffers_count` DMA buffers: with the default
+/// two, the next buffer is written while the previous one is being output.
+///
+/// # Example
+///
+/// ```no_run
+/// use esp_idf_hal::dac::continuous::{Config, ContinuousDac};
+/// use esp_idf_hal::prelude::*;
+///
+/// let peripherals = Peripherals::take().unwrap();
+///
+/// let config = Config::new().sample_rate(44100.Hz());
+/// let mut dac = ContinuousDac::new(peripherals.i2s0, peripherals.pins.gpio25, &config).unwrap();
+/// dac.start().unwrap();
+///
+/// // A 441 Hz sawtooth
+/// let mut samples = [0_u8; 100];
+/// for (index, sample) in samples.iter_mut().enumerate() {
+///     *sample = (index * 256 / 100) as u8;
+/// }
+///
+/// loop {
+///     dac.write_all(&samples, None).unwrap();
+/// }
+/// ```
+#[cfg(any(esp32, all(esp_idf_version_major = "5", not(esp_idf_version = "5.0"))))]
+pub mod continuous {
+    use core::time::Duration;
+
+    use esp_idf_sys::*;
+
+    use crate::gpio::DACPin;
+    use crate::units::*;
+
+    #[cfg(all(esp32, any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0")))]
+    use crate::delay::{portMAX_DELAY, TickType};
+
+    /// Peripheral whose DMA feeds the DAC
+    #[cfg(esp32)]
+    pub type Dma = crate::i2s::I2S0;
+
+    /// Peripheral whose DMA feeds the DAC
+    #[cfg(esp32s2)]
+    pub type Dma = crate::spi::SPI3;
+
+    #[derive(Debug, Copy, Clone)]
+    pub struct Config {
+        /// Samples per second, for each channel
+        pub sample_rate: Hertz,
+        /// Number of samples in a DMA buffer, for each channel
+        pub buffer_len: usize,
+        /// Number of DMA buffers
+        pub buffers_count: usize,
+    }
+
+    impl Config {
+        pub fn new() -> Self {
+            Default::default()
+        }
+
+        #[must_use]
+        pub fn sample_rate(mut self, sample_rate: Hertz) -> Self {
+            self.sample_rate = sample_rate;
+            self
+        }
+
+        #[must_use]
+        pub fn buffer_len(mut self, buffer_len: usize) -> Self {
+            self.buffer_len = buffer_len;
+            self
+        }
+
+        #[must_use]
+        pub fn buffers_count(mut self, buffers_count: usize) -> Self {
+            self.buffers_count = buffers_count;
+            self
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                sample_rate: Hertz(44100),
+                buffer_len: 512,
+                buffers_count: 2,
+            }
+        }
+    }
+
+    /// DAC pins output by a [`ContinuousDac`]: a single pin, or a tuple of
+    /// both, whose samples are then interleaved
+    pub trait Channels {
+        const COUNT: usize;
+
+        /// Bit mask of the DAC channels
+        fn mask(&self) -> u32;
+    }
+
+    impl<P: DACPin> Channels for P {
+        const COUNT: usize = 1;
+
+        fn mask(&self) -> u32 {
+            1 << self.dac_channel()
+        }
+    }
+
+    impl<P1: DACPin, P2: DACPin> Channels for (P1, P2) {
+        const COUNT: usize = 2;
+
+        fn mask(&self) -> u32 {
+            self.0.mask() | self.1.mask()
+        }
+    }
+
+    pub struct ContinuousDac<C: Channels> {
+        dma: Dma,
+        channels: C,
+        running: bool,
+        #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+        handle: dac_continuous_handle_t,
+    }
+
+    unsafe impl<C: Channels + Send> Send for ContinuousDac<C> {}
+
+    impl<C: Channels> ContinuousDac<C> {
+        #[cfg(all(esp32, any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0")))]
+        const PORT: i2s_port_t = i2s_port_t_I2S_NUM_0;
+
+        /// Installs the driver, which outputs nothing until [`Self::start`]
+        pub fn new(dma: Dma, channels: C, config: &Config) -> Result<Self, EspError> {
+            if config.buffer_len == 0
+                || config.buffers_count < 2
+                || C::COUNT == 2 && channels.mask().count_ones() != 2
+            {
+                return Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap());
+            }
+
+            let mut dac = Self {
+                dma,
+                channels,
+                running: false,
+                #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+                handle: core::ptr::null_mut(),
+            };
+
+            dac.install(config)?;
+
+            Ok(dac)
+        }
+
+        pub fn release(mut self) -> Result<(Dma, C), EspError> {
+            self.stop()?;
+            self.uninstall()?;
+
+            Ok((self.dma, self.channels))
+        }
+
+        pub fn start(&mut self) -> Result<(), EspError> {
+            if !self.running {
+                self.start_internal()?;
+                self.running = true;
+            }
+
+            Ok(())
+        }
+
+        pub fn stop(&mut self) -> Result<(), EspError> {
+            if self.running {
+                self.stop_internal()?;
+                self.running = false;
+            }
+
+            Ok(())
+        }
+
+        /// Queues `samples` for output and returns how many were queued
+        ///
+        /// With two channels, the samples are interleaved, starting with the
+        /// first pin of the tuple. Waits up to `timeout` for a free DMA buffer;
+        /// `None` waits forever.
+        pub fn write(
+            &mut self,
+            samples: &[u8],
+            timeout: Option<Duration>,
+        ) -> Result<usize, EspError> {
+            // Whole frames only, so that the channels stay in step
+            let samples = &samples[..samples.len() - samples.len() % C::COUNT];
+
+            self.write_internal(samples, timeout)
+        }
+
+        /// Queues all of `samples`, failing with `ESP_ERR_TIMEOUT` if no DMA
+        /// buffer gets free within `timeout`
+        pub fn write_all(
+            &mut self,
+            mut samples: &[u8],
+            timeout: Option<Duration>,
+        ) -> Result<(), EspError> {
+            while samples.len() >= C::COUNT {
+                let written = self.write(samples, timeout)?;
+                if written == 0 {
+                    return Err(EspError::from(ESP_ERR_TIMEOUT as i32).unwrap());
+                }
+
+                samples = &samples[written..];
+            }
+
+            Ok(())
+        }
+
+        #[cfg(all(esp32, any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0")))]
+        fn install(&mut self, config: &Config) -> Result<(), EspError> {
+            let i2s_config = i2s_config_t {
+                mode: i2s_mode_t_I2S_MODE_MASTER
+                    | i2s_mode_t_I2S_MODE_TX
+                    | i2s_mode_t_I2S_MODE_DAC_BUILT_IN,
+                sample_rate: config.sample_rate.0,
+                bits_per_sample: i2s_bits_per_sample_t_I2S_BITS_PER_SAMPLE_16BIT,
+                channel_format: if C::COUNT == 2 {
+                    i2s_channel_fmt_t_I2S_CHANNEL_FMT_RIGHT_LEFT
+                } else {
+                    i2s_channel_fmt_t_I2S_CHANNEL_FMT_ONLY_RIGHT
+                },
+                communication_format: i2s_comm_format_t_I2S_COMM_FORMAT_STAND_MSB,
+                dma_buf_count: config.buffers_count as _,
+                dma_buf_len: config.buffer_len as _,
+                tx_desc_auto_clear: true,
+                ..Default::default()
+            };
+
+            esp!(unsafe { i2s_driver_install(Self::PORT, &i2s_config, 0, core::ptr::null_mut()) })?;
+
+            // DAC1 is the right channel of I2S0 and DAC2 the left one
+            let dac_mode = match self.channels.mask() {
+                0b01 => i2s_dac_mode_t_I2S_DAC_CHANNEL_RIGHT_EN,
+                0b10 => i2s_dac_mode_t_I2S_DAC_CHANNEL_LEFT_EN,
+                _ => i2s_dac_mode_t_I2S_DAC_CHANNEL_BOTH_EN,
+            };
+
+            let res = esp!(unsafe { i2s_set_pin(Self::PORT, core::ptr::null()) })
+                .and_then(|_| esp!(unsafe { i2s_set_dac_mode(dac_mode) }))
+                .and_then(|_| esp!(unsafe { i2s_stop(Self::PORT) }))
+                .and_then(|_| esp!(unsafe { i2s_zero_dma_buffer(Self::PORT) }));
+
+            if let Err(e) = res {
+                esp!(unsafe { i2s_driver_uninstall(Self::PORT) })?;
+
+                return Err(e);
+            }
+
+            Ok(())
+        }
+
+        #[cfg(all(esp32, any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0")))]
+        fn uninstall(&mut self) -> Result<(), EspError> {
+            esp!(unsafe { i2s_set_dac_mode(i2s_dac_mode_t_I2S_DAC_CHANNEL_DISABLE) })?;
+            esp!(unsafe { i2s_driver_uninstall(Self::PORT) })
+        }
+
+        #[cfg(all(esp32, any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0")))]
+        fn start_internal(&mut self) -> Result<(), EspError> {
+            esp!(unsafe { i2s_start(Self::PORT) })
+        }
+
+        #[cfg(all(esp32, any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0")))]
+        fn stop_internal(&mut self) -> Result<(), EspError> {
+            esp!(unsafe { i2s_stop(Self::PORT) })
+        }
+
+        #[cfg(all(esp32, any(not(esp_idf_version_major = "5"), esp_idf_version = "5.0")))]
+        fn write_internal(
+            &mut self,
+            samples: &[u8],
+            timeout: Option<Duration>,
+        ) -> Result<usize, EspError> {
+            let ticks = timeout.map_or(portMAX_DELAY, |timeout| TickType::from(timeout).0);
+
+            // The DAC takes the upper byte of 16-bit I2S samples
+            let mut buffer = [0_u16; 64];
+            let mut written = 0;
+
+            for chunk in samples.chunks(buffer.len()) {
+                for (sample, value) in buffer.iter_mut().zip(chunk) {
+                    *sample = (*value as u16) << 8;
+                }
+
+                // The I2S FIFO outputs the upper half-word of each 32-bit
+                // word first, i.e. to the right channel (DAC1)
+                if C::COUNT == 2 {
+                    for frame in buffer[..chunk.len()].chunks_exact_mut(2) {
+                        frame.swap(0, 1);
+                    }
+                }
+
+                let mut bytes = 0;
+
+                esp!(unsafe {
+                    i2s_write(
+                        Self::PORT,
+                        buffer.as_ptr() as *const _,
+                        chunk.len() * 2,
+                        &mut bytes,
+                        ticks,
+                    )
+                })?;
+
+                // Partially written frames are not output before the rest
+                // arrives, so only whole frames are reported
+                written += bytes / 2;
+                if bytes < chunk.len() * 2 {
+                    return Ok(written - written % C::COUNT);
+                }
+            }
+
+            Ok(written)
+        }
+
+        #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+        fn install(&mut self, config: &Config) -> Result<(), EspError> {
+            let dac_config = dac_continuous_config_t {
+                chan_mask: self.channels.mask(),
+                desc_num: config.buffers_count as _,
+                buf_size: config.buffer_len * C::COUNT,
+                freq_hz: config.sample_rate.0 * C::COUNT as u32,
+                offset: 0,
+                clk_src: soc_periph_dac_digi_clk_src_t_DAC_DIGI_CLK_SRC_DEFAULT,
+                chan_mode: if C::COUNT == 2 {
+                    dac_continuous_channel_mode_t_DAC_CHANNEL_MODE_ALTER
+                } else {
+                    dac_continuous_channel_mode_t_DAC_CHANNEL_MODE_SIMUL
+                },
+            };
+
+            esp!(unsafe { dac_continuous_new_channels(&dac_config, &mut self.handle) })
+        }
+
+        #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+        fn uninstall(&mut self) -> Result<(), EspError> {
+            esp!(unsafe { dac_continuous_del_channels(self.handle) })
+        }
+
+        #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+        fn start_internal(&mut self) -> Result<(), EspError> {
+            esp!(unsafe { dac_continuous_enable(self.handle) })
+        }
+
+        #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+        fn stop_internal(&mut self) -> Result<(), EspError> {
+            esp!(unsafe { dac_continuous_disable(self.handle) })
+        }
+
+        #[cfg(all(esp_idf_version_major = "5", not(esp_idf_version = "5.0")))]
+        fn write_internal(
+            &mut self,
+            samples: &[u8],
+            timeout: Option<Duration>,
+        ) -> Result<usize, EspError> {
+            let mut written = 0;
+
+            let res = unsafe {
+                dac_continuous_write(
+                    self.handle,
+                    samples.as_ptr() as *mut _,
+                    samples.len(),
+                    &mut written,
+                    timeout.map_or(-1, |timeout| timeout.as_millis().min(i32::MAX as _) as i32),
+                )
+            };
+
+            // Whatever fitted before the timeout is queued all the same
+            if res != ESP_ERR_TIMEOUT as i32 {
+                esp!(res)?;
+            }
+
+            Ok(written - written % C::COUNT)
+        }
+    }
+}
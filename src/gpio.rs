@@ -803,8 +803,8 @@ mod chip {
     pin!(Gpio22:22, IO, NORTC:0, NOADC:0, NODAC:0, NOTOUCH:0);
     #[cfg(not(feature = "riscv-ulp-hal"))]
     pin!(Gpio23:23, IO, NORTC:0, NOADC:0, NODAC:0, NOTOUCH:0);
-    pin!(Gpio25:25, IO, RTC:6, ADC2:8, DAC:1, NOTOUCH:0);
-    pin!(Gpio26:26, IO, RTC:7, ADC2:9, DAC:2, NOTOUCH:0);
+    pin!(Gpio25:25, IO, RTC:6, ADC2:8, DAC:dac_channel_t_DAC_CHANNEL_1, NOTOUCH:0);
+    pin!(Gpio26:26, IO, RTC:7, ADC2:9, DAC:dac_channel_t_DAC_CHANNEL_2, NOTOUCH:0);
     pin!(Gpio27:27, IO, RTC:17, ADC2:7, NODAC:0, TOUCH:7);
     pin!(Gpio32:32, IO, RTC:9, ADC1:4, NODAC:0, TOUCH:9);
     pin!(Gpio33:33, IO, RTC:8, ADC1:5, NODAC:0, TOUCH:8);
@@ -960,11 +960,11 @@ mod chip {
     pin!(Gpio15:15, IO, RTC:15, ADC2:4, NODAC:0, NOTOUCH:0);
     pin!(Gpio16:16, IO, RTC:16, ADC2:5, NODAC:0, NOTOUCH:0);
     #[cfg(esp32s2)]
-    pin!(Gpio17:17, IO, RTC:17, ADC2:6, DAC:1, NOTOUCH:0);
+    pin!(Gpio17:17, IO, RTC:17, ADC2:6, DAC:dac_channel_t_DAC_CHANNEL_1, NOTOUCH:0);
     #[cfg(esp32s3)]
     pin!(Gpio17:17, IO, RTC:17, ADC2:6, NODAC:0, NOTOUCH:0);
     #[cfg(esp32s2)]
-    pin!(Gpio18:18, IO, RTC:18, ADC2:7, DAC:2, NOTOUCH:0);
+    pin!(Gpio18:18, IO, RTC:18, ADC2:7, DAC:dac_channel_t_DAC_CHANNEL_2, NOTOUCH:0);
     #[cfg(esp32s3)]
     pin!(Gpio18:18, IO, RTC:18, ADC2:7, NODAC:0, NOTOUCH:0);
     pin!(Gpio19:19, IO, RTC:19, ADC2:8, NODAC:0, NOTOUCH:0);
@@ -1,6 +1,10 @@
+use core::time::Duration;
+
 use esp_idf_sys::*;
 
-use crate::{delay::*, gpio::*, units::*};
+use embedded_hal::i2c::blocking::Operation;
+
+use crate::{delay::*, gpio::*, mutex, units::*};
 
 crate::embedded_hal_error!(
     I2cError,
@@ -22,6 +26,39 @@ pub struct SlavePins<SDA: OutputPin + InputPin, SCL: InputPin> {
 pub mod config {
     use crate::units::*;
     use core::time::Duration;
+    use esp_idf_sys::*;
+
+    /// SDA sample and hold times, in APB clock cycles
+    ///
+    /// `sample_time` is the delay between the rising edge of SCL and the
+    /// sampling of SDA; `hold_time` is the delay between the falling edge of
+    /// SCL and a change of SDA.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub struct DataTiming {
+        pub sample_time: i32,
+        pub hold_time: i32,
+    }
+
+    /// Setup and hold times of a START or STOP condition, in APB clock cycles
+    ///
+    /// `setup_time` is the delay between SCL going high and the SDA edge of
+    /// the condition; `hold_time` is the delay between that SDA edge and the
+    /// next SCL edge.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub struct ConditionTiming {
+        pub setup_time: i32,
+        pub hold_time: i32,
+    }
+
+    /// High and low periods of SCL, in APB clock cycles
+    ///
+    /// Overrides the periods derived from the baudrate, e.g. to produce an
+    /// asymmetric duty cycle.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub struct ClockPeriod {
+        pub high_period: i32,
+        pub low_period: i32,
+    }
 
     /// I2C Master configuration
     #[derive(Copy, Clone)]
@@ -30,6 +67,14 @@ pub mod config {
         pub timeout: Option<Duration>,
         pub sda_pullup_enabled: bool,
         pub scl_pullup_enabled: bool,
+        pub auto_recover: bool,
+        pub scl_timeout: Option<i32>,
+        pub glitch_filter: Option<u8>,
+        pub data_timing: Option<DataTiming>,
+        pub start_timing: Option<ConditionTiming>,
+        pub stop_timing: Option<ConditionTiming>,
+        pub clock_period: Option<ClockPeriod>,
+        pub intr_flags: u32,
     }
 
     impl MasterConfig {
@@ -60,6 +105,73 @@ pub mod config {
             self.scl_pullup_enabled = enable;
             self
         }
+
+        /// Run a bus recovery whenever a transaction times out
+        #[must_use]
+        pub fn auto_recover(mut self, enable: bool) -> Self {
+            self.auto_recover = enable;
+            self
+        }
+
+        /// Hardware timeout for a slave stretching SCL, in APB clock cycles
+        ///
+        /// `None` keeps the ESP-IDF default.
+        #[must_use]
+        pub fn scl_timeout(mut self, cycles: Option<i32>) -> Self {
+            self.scl_timeout = cycles;
+            self
+        }
+
+        /// Ignore glitches on SCL and SDA shorter than the given number of
+        /// APB clock cycles (0 - 7)
+        #[must_use]
+        pub fn glitch_filter(mut self, cycles: Option<u8>) -> Self {
+            self.glitch_filter = cycles;
+            self
+        }
+
+        #[must_use]
+        pub fn data_timing(mut self, timing: Option<DataTiming>) -> Self {
+            self.data_timing = timing;
+            self
+        }
+
+        #[must_use]
+        pub fn start_timing(mut self, timing: Option<ConditionTiming>) -> Self {
+            self.start_timing = timing;
+            self
+        }
+
+        #[must_use]
+        pub fn stop_timing(mut self, timing: Option<ConditionTiming>) -> Self {
+            self.stop_timing = timing;
+            self
+        }
+
+        #[must_use]
+        pub fn clock_period(mut self, period: Option<ClockPeriod>) -> Self {
+            self.clock_period = period;
+            self
+        }
+
+        /// `ESP_INTR_FLAG_*` flags used when allocating the driver interrupt
+        #[must_use]
+        pub fn intr_flags(mut self, flags: u32) -> Self {
+            self.intr_flags = flags;
+            self
+        }
+
+        /// Allocate the driver interrupt in IRAM, so that it keeps running
+        /// while the flash cache is disabled
+        #[must_use]
+        pub fn iram_safe(mut self, enable: bool) -> Self {
+            if enable {
+                self.intr_flags |= ESP_INTR_FLAG_IRAM;
+            } else {
+                self.intr_flags &= !ESP_INTR_FLAG_IRAM;
+            }
+            self
+        }
     }
 
     impl Default for MasterConfig {
@@ -69,6 +181,14 @@ pub mod config {
                 timeout: None,
                 sda_pullup_enabled: true,
                 scl_pullup_enabled: true,
+                auto_recover: false,
+                scl_timeout: None,
+                glitch_filter: None,
+                data_timing: None,
+                start_timing: None,
+                stop_timing: None,
+                clock_period: None,
+                intr_flags: 0,
             }
         }
     }
@@ -132,6 +252,8 @@ pub mod config {
     }
 }
 
+pub mod register;
+
 pub trait I2c: Send {
     fn port() -> i2c_port_t;
 }
@@ -146,6 +268,7 @@ where
 {
     i2c: I2C,
     pins: MasterPins<SDA, SCL>,
+    config: config::MasterConfig,
     timeout: TickType_t,
 }
 
@@ -178,6 +301,49 @@ where
             return Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap());
         }
 
+        Self::install(&pins, &config)?;
+
+        Ok(Master {
+            i2c,
+            pins,
+            config,
+            timeout: TickType::from(config.timeout).0,
+        })
+    }
+
+    pub fn release(self) -> Result<(I2C, MasterPins<SDA, SCL>), EspError> {
+        esp!(unsafe { i2c_driver_delete(I2C::port()) })?;
+
+        //self.pins.sda.reset()?;
+        //self.pins.scl.reset()?;
+
+        Ok((self.i2c, self.pins))
+    }
+
+    /// Attempts to free a bus whose SDA line is held low by a slave
+    ///
+    /// This is typically needed when the master was reset in the middle of a
+    /// read transaction. The driver is uninstalled, SCL is clocked manually
+    /// (at most 9 times) until the slave releases SDA, a STOP condition is
+    /// generated and the driver is installed again.
+    ///
+    /// Returns `ESP_FAIL` if SDA is still held low after the recovery
+    /// sequence; the driver is reinstalled in either case.
+    pub fn recover(&mut self) -> Result<(), EspError> {
+        esp!(unsafe { i2c_driver_delete(I2C::port()) })?;
+
+        let released = Self::clock_out(&self.pins, &self.config);
+
+        Self::install(&self.pins, &self.config)?;
+
+        if released? {
+            Ok(())
+        } else {
+            Err(EspError::from(ESP_FAIL).unwrap())
+        }
+    }
+
+    fn install(pins: &MasterPins<SDA, SCL>, config: &config::MasterConfig) -> Result<(), EspError> {
         let sys_config = i2c_config_t {
             mode: i2c_mode_t_I2C_MODE_MASTER,
             sda_io_num: pins.sda.pin(),
@@ -200,193 +366,623 @@ where
                 i2c_mode_t_I2C_MODE_MASTER,
                 0, // Not used in master mode
                 0, // Not used in master mode
-                0,
-            ) // TODO: set flags
+                config.intr_flags as i32,
+            )
         })?;
 
-        Ok(Master {
-            i2c,
-            pins,
-            timeout: TickType::from(config.timeout).0,
-        })
+        let tuned = Self::tune(config);
+        if tuned.is_err() {
+            unsafe { i2c_driver_delete(I2C::port()) };
+        }
+
+        tuned
     }
 
-    pub fn release(self) -> Result<(I2C, MasterPins<SDA, SCL>), EspError> {
-        esp!(unsafe { i2c_driver_delete(I2C::port()) })?;
+    fn tune(config: &config::MasterConfig) -> Result<(), EspError> {
+        if let Some(cycles) = config.scl_timeout {
+            esp!(unsafe { i2c_set_timeout(I2C::port(), cycles) })?;
+        }
 
-        //self.pins.sda.reset()?;
-        //self.pins.scl.reset()?;
+        if let Some(cycles) = config.glitch_filter {
+            esp!(unsafe { i2c_filter_enable(I2C::port(), cycles) })?;
+        }
 
-        Ok((self.i2c, self.pins))
+        if let Some(timing) = config.data_timing {
+            esp!(unsafe {
+                i2c_set_data_timing(I2C::port(), timing.sample_time, timing.hold_time)
+            })?;
+        }
+
+        if let Some(timing) = config.start_timing {
+            esp!(unsafe {
+                i2c_set_start_timing(I2C::port(), timing.setup_time, timing.hold_time)
+            })?;
+        }
+
+        if let Some(timing) = config.stop_timing {
+            esp!(unsafe { i2c_set_stop_timing(I2C::port(), timing.setup_time, timing.hold_time) })?;
+        }
+
+        if let Some(period) = config.clock_period {
+            esp!(unsafe { i2c_set_period(I2C::port(), period.high_period, period.low_period) })?;
+        }
+
+        Ok(())
     }
-}
 
-impl<I2C, SDA, SCL> embedded_hal_0_2::blocking::i2c::Read for Master<I2C, SDA, SCL>
-where
-    I2C: I2c,
-    SDA: OutputPin + InputPin,
-    SCL: OutputPin,
-{
-    type Error = I2cError;
+    /// Bit-bangs up to 9 SCL pulses until SDA goes high, followed by a STOP
+    /// condition. Returns whether SDA was released.
+    fn clock_out(
+        pins: &MasterPins<SDA, SCL>,
+        config: &config::MasterConfig,
+    ) -> Result<bool, EspError> {
+        // Half of a 100 kHz clock period; slow enough for any slave
+        const HALF_PERIOD_US: u32 = 5;
+
+        let sda = pins.sda.pin();
+        let scl = pins.scl.pin();
+
+        let gpio_config = gpio_config_t {
+            pin_bit_mask: (1_u64 << sda) | (1_u64 << scl),
+            mode: gpio_mode_t_GPIO_MODE_INPUT_OUTPUT_OD,
+            pull_up_en: (config.sda_pullup_enabled || config.scl_pullup_enabled) as _,
+            ..Default::default()
+        };
+
+        unsafe {
+            esp!(gpio_config(&gpio_config))?;
 
-    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
-        embedded_hal::i2c::blocking::Read::read(self, addr, buffer)
+            esp!(gpio_set_level(sda, 1))?;
+            esp!(gpio_set_level(scl, 1))?;
+            ets_delay_us(HALF_PERIOD_US);
+
+            for _ in 0..9 {
+                if gpio_get_level(sda) != 0 {
+                    break;
+                }
+
+                esp!(gpio_set_level(scl, 0))?;
+                ets_delay_us(HALF_PERIOD_US);
+                esp!(gpio_set_level(scl, 1))?;
+                ets_delay_us(HALF_PERIOD_US);
+            }
+
+            // STOP: SDA goes low-to-high while SCL is high
+            esp!(gpio_set_level(scl, 0))?;
+            ets_delay_us(HALF_PERIOD_US);
+            esp!(gpio_set_level(sda, 0))?;
+            ets_delay_us(HALF_PERIOD_US);
+            esp!(gpio_set_level(scl, 1))?;
+            ets_delay_us(HALF_PERIOD_US);
+            esp!(gpio_set_level(sda, 1))?;
+            ets_delay_us(HALF_PERIOD_US);
+
+            Ok(gpio_get_level(sda) != 0)
+        }
     }
-}
 
-impl<I2C, SDA, SCL> embedded_hal::i2c::blocking::Read for Master<I2C, SDA, SCL>
-where
-    I2C: I2c,
-    SDA: OutputPin + InputPin,
-    SCL: OutputPin,
-{
-    type Error = I2cError;
+    /// Executes the command link, running a bus recovery if it timed out and
+    /// automatic recovery is enabled
+    fn cmd_begin(&mut self, command_link: &CommandLink) -> Result<(), EspError> {
+        let result =
+            esp!(unsafe { i2c_master_cmd_begin(I2C::port(), command_link.0, self.timeout) });
 
-    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
-        let command_link = CommandLink::new().map_err(I2cError::other)?;
+        if let Err(e) = result {
+            if e.code() == ESP_ERR_TIMEOUT as i32 && self.config.auto_recover {
+                // The original timeout is what the caller needs to see
+                let _ = self.recover();
+            }
+        }
 
-        unsafe {
-            esp!(i2c_master_start(command_link.0)).map_err(I2cError::other)?;
-            esp!(i2c_master_write_byte(
-                command_link.0,
-                (addr << 1) | (i2c_rw_t_I2C_MASTER_READ as u8),
-                true
-            ))
-            .map_err(I2cError::other)?;
-            esp!(i2c_master_read(
-                command_link.0,
-                buffer.as_ptr() as *const u8 as *mut u8,
-                buffer.len() as u32,
-                i2c_ack_type_t_I2C_MASTER_LAST_NACK
-            ))
-            .map_err(I2cError::other)?;
-            esp!(i2c_master_stop(command_link.0)).map_err(I2cError::other)?;
-
-            esp_result!(
-                i2c_master_cmd_begin(I2C::port(), command_link.0, self.timeout),
-                ()
-            )
-            .map_err(I2cError::other)
+        result
+    }
+
+    /// Checks whether a device acknowledges the given 7-bit address.
+    ///
+    /// This issues a zero-length write to `addr` and returns `false` if the
+    /// address byte was not acknowledged. Addresses above `0x7f` are rejected
+    /// with `ESP_ERR_INVALID_ARG`.
+    pub fn probe(&mut self, addr: u8) -> Result<bool, EspError> {
+        if addr > 0x7f {
+            return Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap());
+        }
+
+        let command_link = CommandLink::new()?;
+
+        command_link.address(Address::SevenBit(addr), false)?;
+        command_link.stop()?;
+
+        match self.cmd_begin(&command_link) {
+            Ok(_) => Ok(true),
+            Err(e) if e.code() == ESP_FAIL => Ok(false),
+            Err(e) => Err(e),
         }
     }
-}
 
-impl<I2C, SDA, SCL> embedded_hal_0_2::blocking::i2c::Write for Master<I2C, SDA, SCL>
-where
-    I2C: I2c,
-    SDA: OutputPin + InputPin,
-    SCL: OutputPin,
-{
-    type Error = I2cError;
+    /// Probes every non-reserved 7-bit address (`0x08` to `0x77`) and
+    /// returns the set of addresses that acknowledged.
+    pub fn scan(&mut self) -> Result<AddressSet, EspError> {
+        let mut found = AddressSet::new();
+
+        for addr in AddressSet::SCAN_FIRST..=AddressSet::SCAN_LAST {
+            if self.probe(addr)? {
+                found.insert(addr);
+            }
+        }
 
-    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
-        embedded_hal::i2c::blocking::Write::write(self, addr, bytes)
+        Ok(found)
     }
 }
 
-impl<I2C, SDA, SCL> embedded_hal::i2c::blocking::Write for Master<I2C, SDA, SCL>
-where
-    I2C: I2c,
-    SDA: OutputPin + InputPin,
-    SCL: OutputPin,
-{
-    type Error = I2cError;
+/// A set of 7-bit I2C addresses, as returned by [`Master::scan`]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct AddressSet(u128);
 
-    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
-        unsafe {
-            let command_link = CommandLink::new().map_err(I2cError::other)?;
-
-            esp!(i2c_master_start(command_link.0)).map_err(I2cError::other)?;
-            esp!(i2c_master_write_byte(
-                command_link.0,
-                (addr << 1) | (i2c_rw_t_I2C_MASTER_WRITE as u8),
-                true
-            ))
-            .map_err(I2cError::other)?;
-            esp!(i2c_master_write(
-                command_link.0,
-                bytes.as_ptr() as *const u8 as *mut u8,
-                bytes.len() as u32,
-                true
-            ))
-            .map_err(I2cError::other)?;
-            esp!(i2c_master_stop(command_link.0)).map_err(I2cError::other)?;
-
-            esp_result!(
-                i2c_master_cmd_begin(I2C::port(), command_link.0, self.timeout),
-                ()
-            )
-            .map_err(I2cError::other)
+impl AddressSet {
+    const SCAN_FIRST: u8 = 0x08;
+    const SCAN_LAST: u8 = 0x77;
+
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Adds a 7-bit address to the set
+    ///
+    /// Addresses above `0x7f` are not valid 7-bit addresses and are ignored.
+    pub fn insert(&mut self, addr: u8) {
+        if addr < 0x80 {
+            self.0 |= 1 << addr;
         }
     }
-}
 
-impl<I2C, SDA, SCL> embedded_hal_0_2::blocking::i2c::WriteRead for Master<I2C, SDA, SCL>
-where
-    I2C: I2c,
-    SDA: OutputPin + InputPin,
-    SCL: OutputPin,
-{
-    type Error = I2cError;
+    pub fn contains(&self, addr: u8) -> bool {
+        addr < 0x80 && self.0 & (1 << addr) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
 
-    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
-        embedded_hal::i2c::blocking::WriteRead::write_read(self, addr, bytes, buffer)
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..0x80_u8).filter(move |addr| self.contains(*addr))
     }
 }
 
-impl<I2C, SDA, SCL> embedded_hal::i2c::blocking::WriteRead for Master<I2C, SDA, SCL>
+impl<I2C, SDA, SCL> Master<I2C, SDA, SCL>
 where
     I2C: I2c,
     SDA: OutputPin + InputPin,
     SCL: OutputPin,
 {
-    type Error = I2cError;
+    fn read_internal(&mut self, addr: Address, buffer: &mut [u8]) -> Result<(), EspError> {
+        let command_link = CommandLink::new()?;
 
-    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
-        let command_link = CommandLink::new().map_err(I2cError::other)?;
+        command_link.address(addr, true)?;
+        command_link.read(buffer, i2c_ack_type_t_I2C_MASTER_LAST_NACK)?;
+        command_link.stop()?;
 
-        unsafe {
-            esp!(i2c_master_start(command_link.0)).map_err(I2cError::other)?;
-            esp!(i2c_master_write_byte(
-                command_link.0,
-                (addr << 1) | (i2c_rw_t_I2C_MASTER_WRITE as u8),
-                true
-            ))
-            .map_err(I2cError::other)?;
-            esp!(i2c_master_write(
-                command_link.0,
-                bytes.as_ptr() as *const u8 as *mut u8,
-                bytes.len() as u32,
-                true
-            ))
-            .map_err(I2cError::other)?;
-
-            esp!(i2c_master_start(command_link.0)).map_err(I2cError::other)?;
-            esp!(i2c_master_write_byte(
-                command_link.0,
-                (addr << 1) | (i2c_rw_t_I2C_MASTER_READ as u8),
-                true
-            ))
-            .map_err(I2cError::other)?;
-            esp!(i2c_master_read(
-                command_link.0,
-                buffer.as_ptr() as *const u8 as *mut u8,
-                buffer.len() as u32,
-                i2c_ack_type_t_I2C_MASTER_LAST_NACK
-            ))
-            .map_err(I2cError::other)?;
+        self.cmd_begin(&command_link)
+    }
 
-            esp!(i2c_master_stop(command_link.0)).map_err(I2cError::other)?;
+    fn write_internal(&mut self, addr: Address, bytes: &[u8]) -> Result<(), EspError> {
+        let command_link = CommandLink::new()?;
 
-            esp_result!(
-                i2c_master_cmd_begin(I2C::port(), command_link.0, self.timeout),
-                ()
-            )
-            .map_err(I2cError::other)
+        command_link.address(addr, false)?;
+        command_link.write(bytes)?;
+        command_link.stop()?;
+
+        self.cmd_begin(&command_link)
+    }
+
+    fn write_read_internal(
+        &mut self,
+        addr: Address,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), EspError> {
+        let command_link = CommandLink::new()?;
+
+        command_link.address(addr, false)?;
+        command_link.write(bytes)?;
+
+        command_link.address(addr, true)?;
+        command_link.read(buffer, i2c_ack_type_t_I2C_MASTER_LAST_NACK)?;
+
+        command_link.stop()?;
+
+        self.cmd_begin(&command_link)
+    }
+
+    fn write_iter_internal<B>(&mut self, addr: Address, bytes: B) -> Result<(), EspError>
+    where
+        B: IntoIterator<Item = u8>,
+    {
+        let command_link = CommandLink::new()?;
+
+        command_link.address(addr, false)?;
+        for byte in bytes {
+            command_link.write_byte(byte)?;
+        }
+        command_link.stop()?;
+
+        self.cmd_begin(&command_link)
+    }
+
+    fn write_iter_read_internal<B>(
+        &mut self,
+        addr: Address,
+        bytes: B,
+        buffer: &mut [u8],
+    ) -> Result<(), EspError>
+    where
+        B: IntoIterator<Item = u8>,
+    {
+        let command_link = CommandLink::new()?;
+
+        command_link.address(addr, false)?;
+        for byte in bytes {
+            command_link.write_byte(byte)?;
+        }
+
+        command_link.address(addr, true)?;
+        command_link.read(buffer, i2c_ack_type_t_I2C_MASTER_LAST_NACK)?;
+
+        command_link.stop()?;
+
+        self.cmd_begin(&command_link)
+    }
+
+    /// Executes all operations as a single transaction
+    ///
+    /// Adjacent operations of the same kind are merged. A repeated START and
+    /// the slave address are only emitted when switching between writing and
+    /// reading, and only the very last byte of a run of reads is NACKed.
+    ///
+    /// Zero-length reads are skipped, as no read can be issued for them; an
+    /// empty list of operations does nothing.
+    fn transaction_internal<'a, O>(&mut self, addr: Address, operations: O) -> Result<(), EspError>
+    where
+        O: IntoIterator<Item = Operation<'a>>,
+    {
+        let mut operations = operations
+            .into_iter()
+            .filter(|operation| !matches!(operation, Operation::Read(buffer) if buffer.is_empty()))
+            .peekable();
+
+        if operations.peek().is_none() {
+            return Ok(());
+        }
+
+        let command_link = CommandLink::new()?;
+        let mut reading = None;
+
+        while let Some(operation) = operations.next() {
+            let read = matches!(operation, Operation::Read(_));
+
+            if reading != Some(read) {
+                command_link.address(addr, read)?;
+                reading = Some(read);
+            }
+
+            match operation {
+                Operation::Write(bytes) => {
+                    if !bytes.is_empty() {
+                        command_link.write(bytes)?;
+                    }
+                }
+                Operation::Read(buffer) => {
+                    let ack = if matches!(operations.peek(), Some(Operation::Read(_))) {
+                        i2c_ack_type_t_I2C_MASTER_ACK
+                    } else {
+                        i2c_ack_type_t_I2C_MASTER_LAST_NACK
+                    };
+
+                    command_link.read(buffer, ack)?;
+                }
+            }
         }
+
+        command_link.stop()?;
+
+        self.cmd_begin(&command_link)
     }
 }
 
+macro_rules! impl_master {
+    ($address_mode:ty: $address:path) => {
+        impl<I2C, SDA, SCL> embedded_hal_0_2::blocking::i2c::Read<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn read(&mut self, addr: $address_mode, buffer: &mut [u8]) -> Result<(), Self::Error> {
+                embedded_hal::i2c::blocking::Read::read(self, addr, buffer)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal::i2c::blocking::Read<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn read(&mut self, addr: $address_mode, buffer: &mut [u8]) -> Result<(), Self::Error> {
+                self.read_internal($address(addr), buffer)
+                    .map_err(I2cError::other)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal_0_2::blocking::i2c::Write<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn write(&mut self, addr: $address_mode, bytes: &[u8]) -> Result<(), Self::Error> {
+                embedded_hal::i2c::blocking::Write::write(self, addr, bytes)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal::i2c::blocking::Write<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn write(&mut self, addr: $address_mode, bytes: &[u8]) -> Result<(), Self::Error> {
+                self.write_internal($address(addr), bytes)
+                    .map_err(I2cError::other)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal_0_2::blocking::i2c::WriteRead<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn write_read(
+                &mut self,
+                addr: $address_mode,
+                bytes: &[u8],
+                buffer: &mut [u8],
+            ) -> Result<(), Self::Error> {
+                embedded_hal::i2c::blocking::WriteRead::write_read(self, addr, bytes, buffer)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal::i2c::blocking::WriteRead<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn write_read(
+                &mut self,
+                addr: $address_mode,
+                bytes: &[u8],
+                buffer: &mut [u8],
+            ) -> Result<(), Self::Error> {
+                self.write_read_internal($address(addr), bytes, buffer)
+                    .map_err(I2cError::other)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal_0_2::blocking::i2c::WriteIter<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn write<B>(&mut self, addr: $address_mode, bytes: B) -> Result<(), Self::Error>
+            where
+                B: IntoIterator<Item = u8>,
+            {
+                embedded_hal::i2c::blocking::WriteIter::write_iter(self, addr, bytes)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal::i2c::blocking::WriteIter<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn write_iter<B>(&mut self, addr: $address_mode, bytes: B) -> Result<(), Self::Error>
+            where
+                B: IntoIterator<Item = u8>,
+            {
+                self.write_iter_internal($address(addr), bytes)
+                    .map_err(I2cError::other)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal_0_2::blocking::i2c::WriteIterRead<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn write_iter_read<B>(
+                &mut self,
+                addr: $address_mode,
+                bytes: B,
+                buffer: &mut [u8],
+            ) -> Result<(), Self::Error>
+            where
+                B: IntoIterator<Item = u8>,
+            {
+                embedded_hal::i2c::blocking::WriteIterRead::write_iter_read(
+                    self, addr, bytes, buffer,
+                )
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal::i2c::blocking::WriteIterRead<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn write_iter_read<B>(
+                &mut self,
+                addr: $address_mode,
+                bytes: B,
+                buffer: &mut [u8],
+            ) -> Result<(), Self::Error>
+            where
+                B: IntoIterator<Item = u8>,
+            {
+                self.write_iter_read_internal($address(addr), bytes, buffer)
+                    .map_err(I2cError::other)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal_0_2::blocking::i2c::Transactional<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn exec<'a>(
+                &mut self,
+                addr: $address_mode,
+                operations: &mut [embedded_hal_0_2::blocking::i2c::Operation<'a>],
+            ) -> Result<(), Self::Error> {
+                let operations = operations.iter_mut().map(|operation| match operation {
+                    embedded_hal_0_2::blocking::i2c::Operation::Read(buffer) => {
+                        Operation::Read(buffer)
+                    }
+                    embedded_hal_0_2::blocking::i2c::Operation::Write(bytes) => {
+                        Operation::Write(bytes)
+                    }
+                });
+
+                self.transaction_internal($address(addr), operations)
+                    .map_err(I2cError::other)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal::i2c::blocking::Transactional<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn exec<'a>(
+                &mut self,
+                addr: $address_mode,
+                operations: &mut [Operation<'a>],
+            ) -> Result<(), Self::Error> {
+                let operations = operations.iter_mut().map(|operation| match operation {
+                    Operation::Read(buffer) => Operation::Read(buffer),
+                    Operation::Write(bytes) => Operation::Write(bytes),
+                });
+
+                self.transaction_internal($address(addr), operations)
+                    .map_err(I2cError::other)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal_0_2::blocking::i2c::TransactionalIter<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn exec_iter<'a, O>(
+                &mut self,
+                addr: $address_mode,
+                operations: O,
+            ) -> Result<(), Self::Error>
+            where
+                O: IntoIterator<Item = embedded_hal_0_2::blocking::i2c::Operation<'a>>,
+            {
+                let operations = operations.into_iter().map(|operation| match operation {
+                    embedded_hal_0_2::blocking::i2c::Operation::Read(buffer) => {
+                        Operation::Read(buffer)
+                    }
+                    embedded_hal_0_2::blocking::i2c::Operation::Write(bytes) => {
+                        Operation::Write(bytes)
+                    }
+                });
+
+                self.transaction_internal($address(addr), operations)
+                    .map_err(I2cError::other)
+            }
+        }
+
+        impl<I2C, SDA, SCL> embedded_hal::i2c::blocking::TransactionalIter<$address_mode>
+            for Master<I2C, SDA, SCL>
+        where
+            I2C: I2c,
+            SDA: OutputPin + InputPin,
+            SCL: OutputPin,
+        {
+            type Error = I2cError;
+
+            fn exec_iter<'a, O>(
+                &mut self,
+                addr: $address_mode,
+                operations: O,
+            ) -> Result<(), Self::Error>
+            where
+                O: IntoIterator<Item = Operation<'a>>,
+            {
+                self.transaction_internal($address(addr), operations)
+                    .map_err(I2cError::other)
+            }
+        }
+    };
+}
+
+impl_master!(embedded_hal::i2c::SevenBitAddress: Address::SevenBit);
+impl_master!(embedded_hal::i2c::TenBitAddress: Address::TenBit);
+
 impl<I2C, SDA, SCL> Slave<I2C, SDA, SCL>
 where
     I2C: I2c,
@@ -399,19 +995,47 @@ where
         slave_addr: u8,
         config: config::SlaveConfig,
     ) -> Result<Self, EspError> {
-        #[cfg(any(esp_idf_version = "4.4", esp_idf_version_major = "5"))]
-        let sys_config = i2c_config_t {
-            mode: i2c_mode_t_I2C_MODE_SLAVE,
-            sda_io_num: pins.sda.pin(),
-            sda_pullup_en: config.sda_pullup_enabled,
-            scl_io_num: pins.scl.pin(),
-            scl_pullup_en: config.scl_pullup_enabled,
-            __bindgen_anon_1: i2c_config_t__bindgen_ty_1 {
-                slave: i2c_config_t__bindgen_ty_1__bindgen_ty_2 {
-                    slave_addr: slave_addr as u16,
-                    addr_10bit_en: 0, // For now; to become configurable with embedded-hal V1.0
-                    maximum_speed: 0,
-                },
+        if slave_addr > 0x7f {
+            return Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap());
+        }
+
+        Self::install(i2c, pins, slave_addr as u16, false, config)
+    }
+
+    /// Same as [`Self::new`], but responds to a 10-bit slave address
+    pub fn new_ten_bit(
+        i2c: I2C,
+        pins: SlavePins<SDA, SCL>,
+        slave_addr: u16,
+        config: config::SlaveConfig,
+    ) -> Result<Self, EspError> {
+        if slave_addr > 0x3ff {
+            return Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap());
+        }
+
+        Self::install(i2c, pins, slave_addr, true, config)
+    }
+
+    fn install(
+        i2c: I2C,
+        pins: SlavePins<SDA, SCL>,
+        slave_addr: u16,
+        ten_bit_address: bool,
+        config: config::SlaveConfig,
+    ) -> Result<Self, EspError> {
+        #[cfg(any(esp_idf_version = "4.4", esp_idf_version_major = "5"))]
+        let sys_config = i2c_config_t {
+            mode: i2c_mode_t_I2C_MODE_SLAVE,
+            sda_io_num: pins.sda.pin(),
+            sda_pullup_en: config.sda_pullup_enabled,
+            scl_io_num: pins.scl.pin(),
+            scl_pullup_en: config.scl_pullup_enabled,
+            __bindgen_anon_1: i2c_config_t__bindgen_ty_1 {
+                slave: i2c_config_t__bindgen_ty_1__bindgen_ty_2 {
+                    slave_addr,
+                    addr_10bit_en: ten_bit_address as u8,
+                    maximum_speed: 0,
+                },
             },
             ..Default::default()
         };
@@ -425,8 +1049,8 @@ where
             scl_pullup_en: config.scl_pullup_enabled,
             __bindgen_anon_1: i2c_config_t__bindgen_ty_1 {
                 slave: i2c_config_t__bindgen_ty_1__bindgen_ty_2 {
-                    slave_addr: slave_addr as u16,
-                    addr_10bit_en: 0, // For now; to become configurable with embedded-hal V1.0
+                    slave_addr,
+                    addr_10bit_en: ten_bit_address as u8,
                 },
             },
             ..Default::default()
@@ -495,6 +1119,536 @@ where
     }
 }
 
+/// A bus shared between multiple drivers
+///
+/// Wraps a [`Master`] in a [`Mutex`](crate::mutex::Mutex) and hands out
+/// [`BusProxy`] instances, each of which implements the same `embedded-hal`
+/// I2C traits as [`Master`] itself. Every operation executed through a proxy
+/// holds the bus for its whole duration, so transactions of different drivers
+/// never interleave.
+///
+/// To share the proxies between threads, the bus needs to outlive them, e.g.
+/// by placing it in a `static` or leaking it.
+pub struct SharedBus<I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+{
+    master: mutex::Mutex<Master<I2C, SDA, SCL>>,
+}
+
+impl<I2C, SDA, SCL> SharedBus<I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+{
+    pub fn new(master: Master<I2C, SDA, SCL>) -> Self {
+        Self {
+            master: mutex::Mutex::new(master),
+        }
+    }
+
+    pub fn proxy(&self) -> BusProxy<'_, I2C, SDA, SCL> {
+        BusProxy {
+            bus: self,
+            timeout: None,
+        }
+    }
+
+    /// Runs `f` with exclusive access to the bus
+    pub fn lock<R>(&self, f: impl FnOnce(&mut Master<I2C, SDA, SCL>) -> R) -> R {
+        f(&mut self.master.lock())
+    }
+
+    pub fn into_inner(self) -> Master<I2C, SDA, SCL> {
+        self.master.into_inner()
+    }
+}
+
+/// A handle to a [`SharedBus`], to be passed to a single device driver
+pub struct BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+{
+    bus: &'a SharedBus<I2C, SDA, SCL>,
+    timeout: Option<TickType_t>,
+}
+
+impl<'a, I2C, SDA, SCL> BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+{
+    /// Overrides the timeout of the master for operations done through this proxy
+    ///
+    /// `None` waits forever, same as [`config::MasterConfig::timeout`].
+    #[must_use]
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = Some(TickType::from(timeout).0);
+        self
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut Master<I2C, SDA, SCL>) -> R) -> R {
+        self.bus.lock(|master| {
+            let timeout = master.timeout;
+
+            if let Some(proxy_timeout) = self.timeout {
+                master.timeout = proxy_timeout;
+            }
+
+            let result = f(master);
+
+            master.timeout = timeout;
+
+            result
+        })
+    }
+}
+
+impl<'a, I2C, SDA, SCL> Clone for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+{
+    fn clone(&self) -> Self {
+        Self {
+            bus: self.bus,
+            timeout: self.timeout,
+        }
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal_0_2::blocking::i2c::Read<A> for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal_0_2::blocking::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal_0_2::blocking::i2c::Read<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn read(&mut self, addr: A, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.lock(|master| embedded_hal_0_2::blocking::i2c::Read::read(master, addr, buffer))
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal::i2c::blocking::Read<A> for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal::i2c::blocking::Read<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn read(&mut self, addr: A, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.lock(|master| embedded_hal::i2c::blocking::Read::read(master, addr, buffer))
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal_0_2::blocking::i2c::Write<A> for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal_0_2::blocking::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal_0_2::blocking::i2c::Write<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn write(&mut self, addr: A, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.lock(|master| embedded_hal_0_2::blocking::i2c::Write::write(master, addr, bytes))
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal::i2c::blocking::Write<A> for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal::i2c::blocking::Write<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn write(&mut self, addr: A, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.lock(|master| embedded_hal::i2c::blocking::Write::write(master, addr, bytes))
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal_0_2::blocking::i2c::WriteRead<A>
+    for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal_0_2::blocking::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal_0_2::blocking::i2c::WriteRead<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn write_read(&mut self, addr: A, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.lock(|master| {
+            embedded_hal_0_2::blocking::i2c::WriteRead::write_read(master, addr, bytes, buffer)
+        })
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal::i2c::blocking::WriteRead<A> for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal::i2c::blocking::WriteRead<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn write_read(&mut self, addr: A, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.lock(|master| {
+            embedded_hal::i2c::blocking::WriteRead::write_read(master, addr, bytes, buffer)
+        })
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal_0_2::blocking::i2c::WriteIter<A>
+    for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal_0_2::blocking::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal_0_2::blocking::i2c::WriteIter<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn write<B>(&mut self, addr: A, bytes: B) -> Result<(), Self::Error>
+    where
+        B: IntoIterator<Item = u8>,
+    {
+        self.lock(|master| embedded_hal_0_2::blocking::i2c::WriteIter::write(master, addr, bytes))
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal::i2c::blocking::WriteIter<A> for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal::i2c::blocking::WriteIter<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn write_iter<B>(&mut self, addr: A, bytes: B) -> Result<(), Self::Error>
+    where
+        B: IntoIterator<Item = u8>,
+    {
+        self.lock(|master| embedded_hal::i2c::blocking::WriteIter::write_iter(master, addr, bytes))
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal_0_2::blocking::i2c::WriteIterRead<A>
+    for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal_0_2::blocking::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal_0_2::blocking::i2c::WriteIterRead<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn write_iter_read<B>(
+        &mut self,
+        addr: A,
+        bytes: B,
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error>
+    where
+        B: IntoIterator<Item = u8>,
+    {
+        self.lock(|master| {
+            embedded_hal_0_2::blocking::i2c::WriteIterRead::write_iter_read(
+                master, addr, bytes, buffer,
+            )
+        })
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal::i2c::blocking::WriteIterRead<A>
+    for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal::i2c::blocking::WriteIterRead<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn write_iter_read<B>(
+        &mut self,
+        addr: A,
+        bytes: B,
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error>
+    where
+        B: IntoIterator<Item = u8>,
+    {
+        self.lock(|master| {
+            embedded_hal::i2c::blocking::WriteIterRead::write_iter_read(master, addr, bytes, buffer)
+        })
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal_0_2::blocking::i2c::Transactional<A>
+    for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal_0_2::blocking::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal_0_2::blocking::i2c::Transactional<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn exec<'o>(
+        &mut self,
+        addr: A,
+        operations: &mut [embedded_hal_0_2::blocking::i2c::Operation<'o>],
+    ) -> Result<(), Self::Error> {
+        self.lock(|master| {
+            embedded_hal_0_2::blocking::i2c::Transactional::exec(master, addr, operations)
+        })
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal::i2c::blocking::Transactional<A>
+    for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal::i2c::blocking::Transactional<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn exec<'o>(&mut self, addr: A, operations: &mut [Operation<'o>]) -> Result<(), Self::Error> {
+        self.lock(|master| {
+            embedded_hal::i2c::blocking::Transactional::exec(master, addr, operations)
+        })
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal_0_2::blocking::i2c::TransactionalIter<A>
+    for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal_0_2::blocking::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal_0_2::blocking::i2c::TransactionalIter<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn exec_iter<'o, O>(&mut self, addr: A, operations: O) -> Result<(), Self::Error>
+    where
+        O: IntoIterator<Item = embedded_hal_0_2::blocking::i2c::Operation<'o>>,
+    {
+        self.lock(|master| {
+            embedded_hal_0_2::blocking::i2c::TransactionalIter::exec_iter(master, addr, operations)
+        })
+    }
+}
+
+impl<'a, I2C, SDA, SCL, A> embedded_hal::i2c::blocking::TransactionalIter<A>
+    for BusProxy<'a, I2C, SDA, SCL>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin,
+    A: embedded_hal::i2c::AddressMode,
+    Master<I2C, SDA, SCL>: embedded_hal::i2c::blocking::TransactionalIter<A, Error = I2cError>,
+{
+    type Error = I2cError;
+
+    fn exec_iter<'o, O>(&mut self, addr: A, operations: O) -> Result<(), Self::Error>
+    where
+        O: IntoIterator<Item = Operation<'o>>,
+    {
+        self.lock(|master| {
+            embedded_hal::i2c::blocking::TransactionalIter::exec_iter(master, addr, operations)
+        })
+    }
+}
+
+/// An I2C slave emulating a register-addressed peripheral
+///
+/// Every call to [`RegisterSlave::process`] consumes the bytes written by
+/// the master so far and updates the register map. Whenever a write moves the
+/// pointer, or the registers are updated, the hardware transmit FIFO is reset
+/// and refilled with the registers starting at the pointer, so that a
+/// subsequent "write address, then read" returns the expected registers.
+///
+/// The ESP-IDF slave driver neither reports how many bytes a master has read,
+/// nor where a transaction ends, which imposes the following limits:
+///
+/// - The driver's transmit ring buffer cannot be emptied, so at most
+///   [`FIFO_LEN`](Self::FIFO_LEN) registers are queued at a time, which all
+///   go straight to the hardware FIFO. Reads without a write in between
+///   continue after the registers read before, until these are used up.
+/// - A repeated-START `write_read` is not supported: the read starts before
+///   `process` had a chance to see the new pointer, so it returns the
+///   registers queued for the previous one. Masters have to issue the write
+///   and the read as separate transactions, and leave time for `process` to
+///   run in between.
+/// - A pause of at least one tick in the received data is taken as the end
+///   of a write transaction. Write transactions following each other more
+///   closely, or before `process` ran, are merged: the register address of
+///   the second one is then stored as data.
+pub struct RegisterSlave<I2C, SDA, SCL, const N: usize>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: InputPin,
+{
+    slave: Slave<I2C, SDA, SCL>,
+    map: register::RegisterMap<N>,
+}
+
+impl<I2C, SDA, SCL, const N: usize> RegisterSlave<I2C, SDA, SCL, N>
+where
+    I2C: I2c,
+    SDA: OutputPin + InputPin,
+    SCL: InputPin,
+{
+    /// Size of the hardware transmit FIFO, the same on all supported chips
+    pub const FIFO_LEN: usize = 32;
+
+    pub fn new(slave: Slave<I2C, SDA, SCL>, registers: [u8; N]) -> Result<Self, EspError> {
+        let mut this = Self {
+            slave,
+            map: register::RegisterMap::new(registers),
+        };
+
+        this.requeue()?;
+
+        Ok(this)
+    }
+
+    pub fn release(self) -> Slave<I2C, SDA, SCL> {
+        self.slave
+    }
+
+    pub fn registers(&self) -> &[u8; N] {
+        self.map.registers()
+    }
+
+    /// Updates registers locally, e.g. with a new sensor measurement
+    ///
+    /// The transmit FIFO is refilled so that the master reads the new values.
+    pub fn update(&mut self, f: impl FnOnce(&mut [u8; N])) -> Result<(), EspError> {
+        f(self.map.registers_mut());
+
+        self.requeue()
+    }
+
+    /// Waits up to the configured slave timeout for the master to write and
+    /// processes the received write transaction
+    ///
+    /// `on_write` is called for every register written by the master. Returns
+    /// `false` if nothing was received.
+    pub fn process(&mut self, mut on_write: impl FnMut(u8, u8)) -> Result<bool, EspError> {
+        let mut buffer = [0_u8; 32];
+
+        let mut timeout = self.slave.timeout;
+        let mut received = false;
+
+        loop {
+            let n = unsafe {
+                i2c_slave_read_buffer(
+                    I2C::port(),
+                    buffer.as_mut_ptr(),
+                    buffer.len() as u32,
+                    timeout,
+                )
+            };
+
+            if n < 0 {
+                return Err(EspError::from(ESP_FAIL).unwrap());
+            } else if n == 0 {
+                break;
+            }
+
+            if !received {
+                self.map.start();
+                received = true;
+            }
+
+            for byte in &buffer[..n as usize] {
+                self.map.receive(*byte, &mut on_write);
+            }
+
+            // The rest of the transaction follows without a pause
+            timeout = 1;
+        }
+
+        if received {
+            self.requeue()?;
+        }
+
+        Ok(received)
+    }
+
+    /// Replaces the content of the transmit FIFO with the registers starting
+    /// at the pointer
+    ///
+    /// No more than [`Self::FIFO_LEN`] bytes are ever queued after a reset, so
+    /// the driver moves all of them from its ring buffer to the FIFO, and the
+    /// next reset discards them all.
+    fn requeue(&mut self) -> Result<(), EspError> {
+        esp!(unsafe { i2c_reset_tx_fifo(I2C::port()) })?;
+
+        let pointer = self.map.pointer();
+        let registers = self.map.registers();
+
+        let mut queued = 0;
+        let len = N.min(Self::FIFO_LEN);
+
+        while queued < len {
+            let start = (pointer + queued) % N;
+            let chunk = &registers[start..N.min(start + len - queued)];
+
+            let n = unsafe {
+                i2c_slave_write_buffer(
+                    I2C::port(),
+                    chunk.as_ptr() as *const u8 as *mut u8,
+                    chunk.len() as i32,
+                    0,
+                )
+            };
+
+            if n <= 0 {
+                return Err(EspError::from(ESP_FAIL).unwrap());
+            }
+
+            queued += n as usize;
+        }
+
+        Ok(())
+    }
+}
+
 struct CommandLink(i2c_cmd_handle_t);
 
 impl CommandLink {
@@ -507,6 +1661,80 @@ impl CommandLink {
 
         Ok(CommandLink(handle))
     }
+
+    fn start(&self) -> Result<(), EspError> {
+        esp!(unsafe { i2c_master_start(self.0) })
+    }
+
+    fn stop(&self) -> Result<(), EspError> {
+        esp!(unsafe { i2c_master_stop(self.0) })
+    }
+
+    fn write_byte(&self, byte: u8) -> Result<(), EspError> {
+        esp!(unsafe { i2c_master_write_byte(self.0, byte, true) })
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<(), EspError> {
+        esp!(unsafe {
+            i2c_master_write(
+                self.0,
+                bytes.as_ptr() as *const u8 as *mut u8,
+                bytes.len() as u32,
+                true,
+            )
+        })
+    }
+
+    fn read(&self, buffer: &mut [u8], ack: i2c_ack_type_t) -> Result<(), EspError> {
+        esp!(unsafe { i2c_master_read(self.0, buffer.as_mut_ptr(), buffer.len() as u32, ack) })
+    }
+
+    /// Queues a (repeated) START followed by the address of the slave
+    ///
+    /// A 10-bit read is addressed by first sending the full address in write
+    /// mode and then repeating the header byte in read mode. Out of range
+    /// addresses are rejected with `ESP_ERR_INVALID_ARG`.
+    fn address(&self, addr: Address, read: bool) -> Result<(), EspError> {
+        let rw = if read {
+            i2c_rw_t_I2C_MASTER_READ as u8
+        } else {
+            i2c_rw_t_I2C_MASTER_WRITE as u8
+        };
+
+        let valid = match addr {
+            Address::SevenBit(addr) => addr <= 0x7f,
+            Address::TenBit(addr) => addr <= 0x3ff,
+        };
+
+        if !valid {
+            return Err(EspError::from(ESP_ERR_INVALID_ARG as i32).unwrap());
+        }
+
+        self.start()?;
+
+        match addr {
+            Address::SevenBit(addr) => self.write_byte((addr << 1) | rw),
+            Address::TenBit(addr) => {
+                let header = 0b1111_0000 | (((addr >> 8) as u8 & 0b11) << 1);
+
+                self.write_byte(header | (i2c_rw_t_I2C_MASTER_WRITE as u8))?;
+                self.write_byte(addr as u8)?;
+
+                if read {
+                    self.start()?;
+                    self.write_byte(header | rw)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Address {
+    SevenBit(u8),
+    TenBit(u16),
 }
 
 impl Drop for CommandLink {
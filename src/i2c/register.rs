@@ -0,0 +1,202 @@
+//! Register-file logic of an emulated I2C peripheral
+//!
+//! This module does not touch the hardware, see [`RegisterSlave`](super::RegisterSlave)
+//! for the driver built on top of it.
+
+/// Register file of an emulated register-addressed device
+///
+/// Follows the usual convention of I2C sensors and EEPROMs: the first byte
+/// of every write sets the register pointer, any further bytes are stored
+/// starting at that register. Reads return the registers starting at the
+/// pointer. The pointer auto-increments after every byte and wraps around
+/// at the end of the register file.
+pub struct RegisterMap<const N: usize> {
+    registers: [u8; N],
+    pointer: usize,
+    addressed: bool,
+}
+
+impl<const N: usize> RegisterMap<N> {
+    pub const fn new(registers: [u8; N]) -> Self {
+        Self {
+            registers,
+            pointer: 0,
+            addressed: false,
+        }
+    }
+
+    pub fn registers(&self) -> &[u8; N] {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut [u8; N] {
+        &mut self.registers
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    /// Marks the beginning of a new write transaction, so that the next
+    /// received byte is taken as the register address
+    pub fn start(&mut self) {
+        self.addressed = false;
+    }
+
+    /// Processes one byte written by the master
+    ///
+    /// `on_write` is called with the register and its new value for every
+    /// data byte, but not for the register address byte.
+    pub fn receive(&mut self, byte: u8, mut on_write: impl FnMut(u8, u8)) {
+        if N == 0 {
+            return;
+        }
+
+        if self.addressed {
+            let register = self.pointer;
+
+            self.registers[register] = byte;
+            self.advance(1);
+
+            on_write(register as u8, byte);
+        } else {
+            self.pointer = byte as usize % N;
+            self.addressed = true;
+        }
+    }
+
+    /// Processes a complete write transaction
+    pub fn write(&mut self, bytes: &[u8], mut on_write: impl FnMut(u8, u8)) {
+        self.start();
+
+        for byte in bytes {
+            self.receive(*byte, &mut on_write);
+        }
+    }
+
+    /// Fills `buffer` with the registers a master would read now,
+    /// without moving the pointer
+    pub fn peek(&self, buffer: &mut [u8]) {
+        if N == 0 {
+            return;
+        }
+
+        for (offset, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.registers[(self.pointer + offset) % N];
+        }
+    }
+
+    /// Fills `buffer` with the registers a master would read now and
+    /// advances the pointer past them
+    pub fn read(&mut self, buffer: &mut [u8]) {
+        self.peek(buffer);
+        self.advance(buffer.len());
+    }
+
+    fn advance(&mut self, count: usize) {
+        if N > 0 {
+            self.pointer = (self.pointer + count) % N;
+        }
+    }
+}
+
+impl<const N: usize> Default for RegisterMap<N> {
+    fn default() -> Self {
+        Self::new([0; N])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegisterMap;
+
+    #[test]
+    fn write_sets_pointer_and_registers() {
+        let mut map = RegisterMap::new([0_u8; 4]);
+        let mut written = [(0, 0); 4];
+        let mut count = 0;
+
+        map.write(&[1, 0xAA, 0xBB], |register, value| {
+            written[count] = (register, value);
+            count += 1;
+        });
+
+        assert_eq!(map.registers(), &[0, 0xAA, 0xBB, 0]);
+        assert_eq!(&written[..count], &[(1, 0xAA), (2, 0xBB)]);
+        assert_eq!(map.pointer(), 3);
+    }
+
+    #[test]
+    fn address_only_write_moves_pointer() {
+        let mut map = RegisterMap::new([10, 11, 12, 13]);
+
+        map.write(&[2], |_, _| panic!("no register written"));
+
+        let mut buffer = [0; 3];
+        map.read(&mut buffer);
+
+        assert_eq!(buffer, [12, 13, 10]);
+        assert_eq!(map.pointer(), 1);
+    }
+
+    #[test]
+    fn pointer_wraps_around() {
+        let mut map = RegisterMap::new([0_u8; 4]);
+
+        map.write(&[6, 1, 2, 3], |_, _| {});
+
+        assert_eq!(map.registers(), &[3, 0, 1, 2]);
+        assert_eq!(map.pointer(), 1);
+    }
+
+    #[test]
+    fn peek_keeps_pointer() {
+        let mut map = RegisterMap::new([1, 2, 3]);
+        map.write(&[1], |_, _| {});
+
+        let mut buffer = [0; 2];
+        map.peek(&mut buffer);
+
+        assert_eq!(buffer, [2, 3]);
+        assert_eq!(map.pointer(), 1);
+    }
+
+    #[test]
+    fn every_transaction_starts_with_an_address() {
+        let mut map = RegisterMap::new([0_u8; 4]);
+
+        map.write(&[0, 5], |_, _| {});
+        map.write(&[3, 7], |_, _| {});
+
+        assert_eq!(map.registers(), &[5, 0, 0, 7]);
+    }
+
+    #[test]
+    fn bytes_received_one_by_one() {
+        let mut map = RegisterMap::new([0_u8; 4]);
+
+        map.start();
+        for byte in [2, 8, 9] {
+            map.receive(byte, |_, _| {});
+        }
+
+        map.start();
+        map.receive(0, |_, _| {});
+
+        assert_eq!(map.registers(), &[0, 0, 8, 9]);
+        assert_eq!(map.pointer(), 0);
+    }
+
+    #[test]
+    fn empty_map() {
+        let mut map = RegisterMap::new([]);
+
+        map.write(&[1, 2], |_, _| panic!("no register written"));
+
+        let mut buffer = [0xFF; 2];
+        map.read(&mut buffer);
+
+        assert_eq!(buffer, [0xFF; 2]);
+        assert_eq!(map.pointer(), 0);
+    }
+}
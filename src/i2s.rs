@@ -0,0 +1,22 @@
+//! Inter-IC Sound (I2S)
+//!
+//! Currently only the peripheral itself, which [`crate::dac::continuous`]
+//! uses to feed the DAC on the ESP32.
+//!
+//! # TODO
+//! - Standard, PDM and TDM modes
+
+use core::marker::PhantomData;
+
+pub struct I2S0(PhantomData<*const ()>);
+
+impl I2S0 {
+    /// # Safety
+    ///
+    /// Care should be taken not to instantiate this I2S0 instance, if it is already instantiated and used elsewhere
+    pub unsafe fn new() -> Self {
+        I2S0(PhantomData)
+    }
+}
+
+unsafe impl Send for I2S0 {}
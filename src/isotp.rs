@@ -0,0 +1,1276 @@
+//! ISO-TP (ISO 15765-2) transport protocol over CAN.
+//!
+//! Segments payloads of up to 4095 bytes into single, first and consecutive
+//! frames, and drives the flow control handshake (block size and STmin) with
+//! the peer. Only normal addressing is supported, i.e. the whole 8 bytes of a
+//! frame carry the protocol and the two directions use different CAN IDs.
+//!
+//! The segmentation logic lives in [`Sender`] and [`Receiver`], which neither
+//! touch the CAN driver nor read the time themselves, so that they can be used
+//! (and tested) with any CAN stand-in. [`IsoTp`] ties them to anything
+//! implementing the `embedded-hal` non-blocking `Can` trait, e.g. [`crate::can::CanBus`].
+//!
+//! # Example
+//!
+//! ```no_run
+//! use core::time::Duration;
+//!
+//! use embedded_hal::can::StandardId;
+//! use esp_idf_hal::can;
+//! use esp_idf_hal::isotp;
+//! use esp_idf_hal::peripherals::Peripherals;
+//!
+//! let peripherals = Peripherals::take().unwrap();
+//! let can = can::CanBus::new(
+//!     peripherals.can,
+//!     peripherals.pins.gpio5,
+//!     peripherals.pins.gpio4,
+//!     can::config::Config::new(),
+//! )
+//! .unwrap();
+//!
+//! let config = isotp::Config::new(
+//!     StandardId::new(0x7E0).unwrap().into(),
+//!     StandardId::new(0x7E8).unwrap().into(),
+//! );
+//! let mut isotp = isotp::IsoTp::new(can, isotp::EspClock, config);
+//!
+//! // UDS ReadDataByIdentifier, VIN
+//! isotp.send(&[0x22, 0xF1, 0x90]).unwrap();
+//!
+//! let mut response = [0; 64];
+//! let len = isotp.receive(&mut response, Some(Duration::from_secs(1))).unwrap();
+//! ```
+
+use core::time::Duration;
+
+use embedded_hal::can::Id;
+
+/// Largest payload which can be announced in a first frame
+pub const MAX_PAYLOAD_LEN: usize = 4095;
+
+const SINGLE_FRAME: u8 = 0x0;
+const FIRST_FRAME: u8 = 0x1;
+const CONSECUTIVE_FRAME: u8 = 0x2;
+const FLOW_CONTROL: u8 = 0x3;
+
+const FLOW_STATUS_CONTINUE: u8 = 0x0;
+const FLOW_STATUS_WAIT: u8 = 0x1;
+const FLOW_STATUS_OVERFLOW: u8 = 0x2;
+
+/// Violation of the protocol, by either side
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ProtocolError {
+    /// The peer did not send the next flow control or consecutive frame in
+    /// time, or a frame could not be transmitted in time
+    Timeout,
+    /// The peer sent more consecutive WAIT flow control frames than
+    /// [`Config::max_wait_frames`]
+    WaitLimit,
+    /// The payload does not fit into the receive buffer (or the peer's one)
+    Overflow,
+    /// The payload is empty or longer than [`MAX_PAYLOAD_LEN`]
+    InvalidLength,
+    /// A consecutive frame was received out of order
+    InvalidSequence,
+    /// A frame could not be decoded
+    InvalidFrame,
+}
+
+impl core::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ProtocolError {}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Can(E),
+    Protocol(ProtocolError),
+}
+
+impl<E> From<ProtocolError> for Error<E> {
+    fn from(e: ProtocolError) -> Self {
+        Error::Protocol(e)
+    }
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Can(e) => write!(f, "CAN error: {:?}", e),
+            Error::Protocol(e) => write!(f, "ISO-TP error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: core::fmt::Debug> std::error::Error for Error<E> {}
+
+/// ISO-TP configuration
+#[derive(Debug, Copy, Clone)]
+pub struct Config {
+    /// ID of the frames sent to the peer
+    pub tx_id: Id,
+    /// ID of the frames received from the peer
+    pub rx_id: Id,
+    /// Number of consecutive frames the peer may send before waiting for the
+    /// next flow control frame; `0` means no limit
+    pub block_size: u8,
+    /// Minimum separation time between consecutive frames requested from the peer
+    pub st_min: Duration,
+    /// How long to wait for a flow control or the next consecutive frame
+    /// (N_Bs and N_Cr), and for a frame to be accepted by the CAN driver (N_As)
+    pub timeout: Duration,
+    /// Number of consecutive WAIT flow control frames accepted from the peer
+    /// before the transmission is aborted (N_WFTmax)
+    pub max_wait_frames: u8,
+    /// If set, frames are padded to 8 bytes with this value
+    pub padding: Option<u8>,
+}
+
+impl Config {
+    pub fn new(tx_id: Id, rx_id: Id) -> Self {
+        Self {
+            tx_id,
+            rx_id,
+            block_size: 0,
+            st_min: Duration::from_millis(0),
+            timeout: Duration::from_millis(1000),
+            max_wait_frames: 10,
+            padding: Some(0xCC),
+        }
+    }
+
+    #[must_use]
+    pub fn block_size(mut self, block_size: u8) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    #[must_use]
+    pub fn st_min(mut self, st_min: Duration) -> Self {
+        self.st_min = st_min;
+        self
+    }
+
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn max_wait_frames(mut self, max_wait_frames: u8) -> Self {
+        self.max_wait_frames = max_wait_frames;
+        self
+    }
+
+    #[must_use]
+    pub fn padding(mut self, padding: Option<u8>) -> Self {
+        self.padding = padding;
+        self
+    }
+}
+
+/// Payload of a single CAN frame
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FrameData {
+    data: [u8; 8],
+    len: usize,
+}
+
+impl FrameData {
+    fn new(padding: Option<u8>) -> Self {
+        Self {
+            data: [padding.unwrap_or(0); 8],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.data[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn pad(mut self, padding: Option<u8>) -> Self {
+        if padding.is_some() {
+            self.len = self.data.len();
+        }
+
+        self
+    }
+
+    fn flow_control(status: u8, block_size: u8, st_min: Duration, padding: Option<u8>) -> Self {
+        let mut frame = Self::new(padding);
+        frame.push(&[
+            (FLOW_CONTROL << 4) | status,
+            block_size,
+            encode_st_min(st_min),
+        ]);
+
+        frame.pad(padding)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+/// Decodes the STmin byte of a flow control frame
+///
+/// Reserved values are interpreted as the largest valid one (127 ms), as
+/// mandated by the standard.
+pub fn decode_st_min(st_min: u8) -> Duration {
+    match st_min {
+        0x00..=0x7F => Duration::from_millis(st_min as u64),
+        0xF1..=0xF9 => Duration::from_micros((st_min - 0xF0) as u64 * 100),
+        _ => Duration::from_millis(0x7F),
+    }
+}
+
+/// Encodes a separation time as the STmin byte of a flow control frame,
+/// rounding up to the next representable value
+pub fn encode_st_min(st_min: Duration) -> u8 {
+    let micros = st_min.as_micros();
+
+    if micros == 0 {
+        0
+    } else if micros <= 900 {
+        0xF0 + ((micros + 99) / 100) as u8
+    } else {
+        ((micros + 999) / 1000).min(0x7F) as u8
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum SenderState {
+    Start,
+    WaitFlowControl {
+        deadline: Duration,
+    },
+    Consecutive {
+        remaining_in_block: Option<u8>,
+        st_min: Duration,
+        next_at: Duration,
+    },
+    Done,
+}
+
+/// Segmentation state machine of an outgoing payload
+pub struct Sender<'a> {
+    data: &'a [u8],
+    offset: usize,
+    sequence: u8,
+    state: SenderState,
+    wait_frames: u8,
+    timeout: Duration,
+    max_wait_frames: u8,
+    padding: Option<u8>,
+}
+
+impl<'a> Sender<'a> {
+    pub fn new(data: &'a [u8], config: &Config) -> Result<Self, ProtocolError> {
+        if data.is_empty() || data.len() > MAX_PAYLOAD_LEN {
+            return Err(ProtocolError::InvalidLength);
+        }
+
+        Ok(Self {
+            data,
+            offset: 0,
+            sequence: 0,
+            state: SenderState::Start,
+            wait_frames: 0,
+            timeout: config.timeout,
+            max_wait_frames: config.max_wait_frames,
+            padding: config.padding,
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, SenderState::Done)
+    }
+
+    /// Returns the next frame to transmit at time `now`, if any is due
+    pub fn poll(&mut self, now: Duration) -> Result<Option<FrameData>, ProtocolError> {
+        let mut frame = FrameData::new(self.padding);
+
+        match self.state {
+            SenderState::Start if self.data.len() <= 7 => {
+                frame.push(&[(SINGLE_FRAME << 4) | self.data.len() as u8]);
+                frame.push(self.data);
+
+                self.offset = self.data.len();
+                self.state = SenderState::Done;
+            }
+            SenderState::Start => {
+                let len = self.data.len();
+
+                frame.push(&[(FIRST_FRAME << 4) | (len >> 8) as u8, len as u8]);
+                frame.push(&self.data[..6]);
+
+                self.offset = 6;
+                self.sequence = 1;
+                self.state = SenderState::WaitFlowControl {
+                    deadline: now + self.timeout,
+                };
+            }
+            SenderState::WaitFlowControl { deadline } => {
+                return if now >= deadline {
+                    Err(ProtocolError::Timeout)
+                } else {
+                    Ok(None)
+                };
+            }
+            SenderState::Consecutive {
+                remaining_in_block,
+                st_min,
+                next_at,
+            } => {
+                if now < next_at {
+                    return Ok(None);
+                }
+
+                let end = (self.offset + 7).min(self.data.len());
+
+                frame.push(&[(CONSECUTIVE_FRAME << 4) | self.sequence]);
+                frame.push(&self.data[self.offset..end]);
+
+                self.offset = end;
+                self.sequence = (self.sequence + 1) & 0x0F;
+
+                self.state = if self.offset == self.data.len() {
+                    SenderState::Done
+                } else if remaining_in_block == Some(1) {
+                    SenderState::WaitFlowControl {
+                        deadline: now + self.timeout,
+                    }
+                } else {
+                    SenderState::Consecutive {
+                        remaining_in_block: remaining_in_block.map(|remaining| remaining - 1),
+                        st_min,
+                        next_at: now + st_min,
+                    }
+                };
+            }
+            SenderState::Done => return Ok(None),
+        }
+
+        Ok(Some(frame.pad(self.padding)))
+    }
+
+    /// Processes a frame received from the peer
+    ///
+    /// Anything but a flow control frame received while waiting for one is ignored.
+    pub fn on_frame(&mut self, data: &[u8], now: Duration) -> Result<(), ProtocolError> {
+        if !matches!(self.state, SenderState::WaitFlowControl { .. }) {
+            return Ok(());
+        }
+
+        if data.is_empty() || data[0] >> 4 != FLOW_CONTROL {
+            return Ok(());
+        }
+
+        if data.len() < 3 {
+            return Err(ProtocolError::InvalidFrame);
+        }
+
+        match data[0] & 0x0F {
+            FLOW_STATUS_CONTINUE => {
+                self.wait_frames = 0;
+                self.state = SenderState::Consecutive {
+                    remaining_in_block: if data[1] == 0 { None } else { Some(data[1]) },
+                    st_min: decode_st_min(data[2]),
+                    next_at: now,
+                };
+
+                Ok(())
+            }
+            FLOW_STATUS_WAIT => {
+                if self.wait_frames >= self.max_wait_frames {
+                    self.state = SenderState::Done;
+                    return Err(ProtocolError::WaitLimit);
+                }
+
+                self.wait_frames += 1;
+                self.state = SenderState::WaitFlowControl {
+                    deadline: now + self.timeout,
+                };
+
+                Ok(())
+            }
+            FLOW_STATUS_OVERFLOW => Err(ProtocolError::Overflow),
+            _ => Err(ProtocolError::InvalidFrame),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum ReceiverState {
+    Idle,
+    Consecutive { deadline: Duration },
+    Overflow,
+    Done,
+}
+
+/// Reassembly state machine of an incoming payload
+pub struct Receiver<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    received: usize,
+    sequence: u8,
+    block: u8,
+    state: ReceiverState,
+    block_size: u8,
+    st_min: Duration,
+    timeout: Duration,
+    padding: Option<u8>,
+}
+
+impl<'a> Receiver<'a> {
+    pub fn new(buffer: &'a mut [u8], config: &Config) -> Self {
+        Self {
+            buffer,
+            len: 0,
+            received: 0,
+            sequence: 0,
+            block: 0,
+            state: ReceiverState::Idle,
+            block_size: config.block_size,
+            st_min: config.st_min,
+            timeout: config.timeout,
+            padding: config.padding,
+        }
+    }
+
+    /// Processes a frame received from the peer and returns the flow control
+    /// frame to answer with, if any
+    ///
+    /// A new single or first frame aborts a reception in progress and starts
+    /// over, as mandated by the standard.
+    pub fn on_frame(
+        &mut self,
+        data: &[u8],
+        now: Duration,
+    ) -> Result<Option<FrameData>, ProtocolError> {
+        if data.is_empty() || matches!(self.state, ReceiverState::Done | ReceiverState::Overflow) {
+            return Ok(None);
+        }
+
+        match (data[0] >> 4, self.state) {
+            (SINGLE_FRAME, _) => {
+                let len = (data[0] & 0x0F) as usize;
+                if len == 0 || len > 7 || data.len() < 1 + len {
+                    return Err(ProtocolError::InvalidFrame);
+                }
+
+                if len > self.buffer.len() {
+                    self.state = ReceiverState::Overflow;
+                    return Ok(None);
+                }
+
+                self.buffer[..len].copy_from_slice(&data[1..1 + len]);
+                self.len = len;
+                self.received = len;
+                self.state = ReceiverState::Done;
+
+                Ok(None)
+            }
+            (FIRST_FRAME, _) => {
+                if data.len() < 8 {
+                    return Err(ProtocolError::InvalidFrame);
+                }
+
+                let len = (((data[0] & 0x0F) as usize) << 8) | data[1] as usize;
+                if len < 8 {
+                    return Err(ProtocolError::InvalidFrame);
+                }
+
+                if len > self.buffer.len() {
+                    self.state = ReceiverState::Overflow;
+
+                    return Ok(Some(FrameData::flow_control(
+                        FLOW_STATUS_OVERFLOW,
+                        0,
+                        Duration::from_millis(0),
+                        self.padding,
+                    )));
+                }
+
+                self.buffer[..6].copy_from_slice(&data[2..8]);
+                self.len = len;
+                self.received = 6;
+                self.sequence = 1;
+                self.block = 0;
+                self.state = ReceiverState::Consecutive {
+                    deadline: now + self.timeout,
+                };
+
+                Ok(Some(self.continue_to_send()))
+            }
+            (CONSECUTIVE_FRAME, ReceiverState::Consecutive { .. }) => {
+                if data[0] & 0x0F != self.sequence {
+                    self.state = ReceiverState::Idle;
+                    return Err(ProtocolError::InvalidSequence);
+                }
+
+                let count = (self.len - self.received).min(7);
+                if data.len() < 1 + count {
+                    return Err(ProtocolError::InvalidFrame);
+                }
+
+                self.buffer[self.received..self.received + count]
+                    .copy_from_slice(&data[1..1 + count]);
+                self.received += count;
+                self.sequence = (self.sequence + 1) & 0x0F;
+
+                if self.received == self.len {
+                    self.state = ReceiverState::Done;
+                    return Ok(None);
+                }
+
+                self.state = ReceiverState::Consecutive {
+                    deadline: now + self.timeout,
+                };
+
+                if self.block_size == 0 {
+                    return Ok(None);
+                }
+
+                self.block += 1;
+                if self.block == self.block_size {
+                    self.block = 0;
+
+                    Ok(Some(self.continue_to_send()))
+                } else {
+                    Ok(None)
+                }
+            }
+            // Stray consecutive or flow control frames
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the length of the payload once it has been completely received
+    pub fn poll(&mut self, now: Duration) -> Result<Option<usize>, ProtocolError> {
+        match self.state {
+            ReceiverState::Done => Ok(Some(self.len)),
+            ReceiverState::Overflow => Err(ProtocolError::Overflow),
+            ReceiverState::Consecutive { deadline } if now >= deadline => {
+                self.state = ReceiverState::Idle;
+
+                Err(ProtocolError::Timeout)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether a segmented reception is in progress
+    pub fn is_receiving(&self) -> bool {
+        matches!(self.state, ReceiverState::Consecutive { .. })
+    }
+
+    fn continue_to_send(&self) -> FrameData {
+        FrameData::flow_control(
+            FLOW_STATUS_CONTINUE,
+            self.block_size,
+            self.st_min,
+            self.padding,
+        )
+    }
+}
+
+/// Monotonic time source used for the protocol timeouts
+pub trait Clock {
+    fn now(&self) -> Duration;
+
+    /// Called by [`IsoTp`] whenever there is nothing to transmit and no frame
+    /// has been received, before polling the CAN driver again
+    ///
+    /// Implementations should give up the CPU for a short while (rather than
+    /// return immediately), so that waiting for the peer does not starve
+    /// lower priority tasks.
+    fn idle(&self);
+}
+
+/// [`Clock`] based on the ESP-IDF high resolution timer
+///
+/// Idles by delaying the task for one FreeRTOS tick. Frames arriving in the
+/// meantime are buffered by the CAN driver, so its RX queue should be able to
+/// hold the frames the peer sends within a tick (see
+/// [`Config::block_size`] and [`Config::st_min`]).
+#[cfg(feature = "esp-idf-sys")]
+pub struct EspClock;
+
+#[cfg(feature = "esp-idf-sys")]
+impl Clock for EspClock {
+    fn now(&self) -> Duration {
+        Duration::from_micros(unsafe { esp_idf_sys::esp_timer_get_time() } as u64)
+    }
+
+    fn idle(&self) {
+        unsafe { esp_idf_sys::vTaskDelay(1) };
+    }
+}
+
+/// ISO-TP transport over a CAN driver
+pub struct IsoTp<CAN, CLOCK> {
+    can: CAN,
+    clock: CLOCK,
+    config: Config,
+}
+
+impl<CAN, CLOCK> IsoTp<CAN, CLOCK>
+where
+    CAN: embedded_hal::can::nb::Can,
+    CLOCK: Clock,
+{
+    pub fn new(can: CAN, clock: CLOCK, config: Config) -> Self {
+        Self { can, clock, config }
+    }
+
+    pub fn release(self) -> (CAN, CLOCK) {
+        (self.can, self.clock)
+    }
+
+    /// Sends `data` to the peer, segmenting it as necessary
+    pub fn send(&mut self, data: &[u8]) -> Result<(), Error<CAN::Error>> {
+        let mut sender = Sender::new(data, &self.config)?;
+
+        loop {
+            let sent = if let Some(frame) = sender.poll(self.clock.now())? {
+                self.transmit(&frame)?;
+                true
+            } else {
+                false
+            };
+
+            if sender.is_done() {
+                return Ok(());
+            }
+
+            if let Some(frame) = self.receive_frame()? {
+                sender.on_frame(frame.as_slice(), self.clock.now())?;
+            } else if !sent {
+                self.clock.idle();
+            }
+        }
+    }
+
+    /// Receives a payload from the peer into `buffer` and returns its length
+    ///
+    /// `timeout` limits the wait for the first frame; `None` waits forever.
+    pub fn receive(
+        &mut self,
+        buffer: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> Result<usize, Error<CAN::Error>> {
+        let deadline = timeout.map(|timeout| self.clock.now() + timeout);
+
+        let config = self.config;
+        let mut receiver = Receiver::new(buffer, &config);
+
+        loop {
+            let now = self.clock.now();
+
+            if let Some(len) = receiver.poll(now)? {
+                return Ok(len);
+            }
+
+            if !receiver.is_receiving() && deadline.map_or(false, |deadline| now >= deadline) {
+                return Err(ProtocolError::Timeout.into());
+            }
+
+            if let Some(frame) = self.receive_frame()? {
+                if let Some(flow_control) = receiver.on_frame(frame.as_slice(), self.clock.now())? {
+                    self.transmit(&flow_control)?;
+                }
+            } else {
+                self.clock.idle();
+            }
+        }
+    }
+
+    fn transmit(&mut self, data: &FrameData) -> Result<(), Error<CAN::Error>> {
+        let frame =
+            <CAN::Frame as embedded_hal::can::Frame>::new(self.config.tx_id, data.as_slice())
+                .ok_or(ProtocolError::InvalidFrame)?;
+
+        let deadline = self.clock.now() + self.config.timeout;
+
+        loop {
+            match self.can.transmit(&frame) {
+                Ok(_) => return Ok(()),
+                Err(nb::Error::WouldBlock) if self.clock.now() >= deadline => {
+                    return Err(ProtocolError::Timeout.into())
+                }
+                Err(nb::Error::WouldBlock) => self.clock.idle(),
+                Err(nb::Error::Other(e)) => return Err(Error::Can(e)),
+            }
+        }
+    }
+
+    fn receive_frame(&mut self) -> Result<Option<FrameData>, Error<CAN::Error>> {
+        use embedded_hal::can::Frame;
+
+        match self.can.receive() {
+            Ok(frame) if frame.is_data_frame() && frame.id() == self.config.rx_id => {
+                let mut data = FrameData::new(None);
+                data.push(frame.data());
+
+                Ok(Some(data))
+            }
+            Ok(_) | Err(nb::Error::WouldBlock) => Ok(None),
+            Err(nb::Error::Other(e)) => Err(Error::Can(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use embedded_hal::can::{ErrorKind, Id, StandardId};
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct TestFrame {
+        id: Id,
+        data: Vec<u8>,
+    }
+
+    impl embedded_hal::can::Frame for TestFrame {
+        fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+            if data.len() <= 8 {
+                Some(Self {
+                    id: id.into(),
+                    data: data.to_vec(),
+                })
+            } else {
+                None
+            }
+        }
+
+        fn new_remote(_id: impl Into<Id>, _dlc: usize) -> Option<Self> {
+            None
+        }
+
+        fn is_extended(&self) -> bool {
+            matches!(self.id, Id::Extended(_))
+        }
+
+        fn is_remote_frame(&self) -> bool {
+            false
+        }
+
+        fn id(&self) -> Id {
+            self.id
+        }
+
+        fn dlc(&self) -> usize {
+            self.data.len()
+        }
+
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+    }
+
+    #[derive(Debug)]
+    enum TestError {}
+
+    impl embedded_hal::can::Error for TestError {
+        fn kind(&self) -> ErrorKind {
+            match *self {}
+        }
+    }
+
+    type TestResult<T> = Result<T, Error<TestError>>;
+
+    /// Manual clock, which only advances while the node under test idles
+    #[derive(Clone, Default)]
+    struct TestClock(Rc<Cell<Duration>>);
+
+    impl TestClock {
+        const TICK: Duration = Duration::from_micros(100);
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> Duration {
+            self.0.get()
+        }
+
+        fn idle(&self) {
+            self.0.set(self.0.get() + Self::TICK);
+        }
+    }
+
+    /// State machine of the node on the other end of the bus
+    enum Peer<'a> {
+        /// Neither answers nor takes the frames off the bus
+        Silent,
+        Receiver(Receiver<'a>),
+        Sender(Sender<'a>),
+    }
+
+    /// In-memory CAN bus between the node under test and a [`Peer`], which
+    /// is stepped whenever the node under test accesses the bus
+    struct TestCan<'a> {
+        peer: Peer<'a>,
+        peer_id: Id,
+        peer_error: Option<ProtocolError>,
+        clock: TestClock,
+        /// Frames left on the bus by a silent peer
+        pending: usize,
+        rx: VecDeque<TestFrame>,
+    }
+
+    impl<'a> TestCan<'a> {
+        const CAPACITY: usize = 4;
+
+        fn new(peer: Peer<'a>, peer_config: &Config, clock: &TestClock) -> Self {
+            Self {
+                peer,
+                peer_id: peer_config.tx_id,
+                peer_error: None,
+                clock: clock.clone(),
+                pending: 0,
+                rx: VecDeque::new(),
+            }
+        }
+
+        fn answer(&mut self, answer: Result<Option<FrameData>, ProtocolError>) {
+            match answer {
+                Ok(Some(data)) => self.rx.push_back(TestFrame {
+                    id: self.peer_id,
+                    data: data.as_slice().to_vec(),
+                }),
+                Ok(None) => (),
+                Err(e) => {
+                    self.peer_error.get_or_insert(e);
+                }
+            }
+        }
+    }
+
+    impl embedded_hal::can::nb::Can for TestCan<'_> {
+        type Frame = TestFrame;
+        type Error = TestError;
+
+        fn transmit(&mut self, frame: &TestFrame) -> nb::Result<Option<TestFrame>, TestError> {
+            let now = self.clock.now();
+
+            let answer = match &mut self.peer {
+                Peer::Silent if self.pending == Self::CAPACITY => {
+                    return Err(nb::Error::WouldBlock)
+                }
+                Peer::Silent => {
+                    self.pending += 1;
+                    Ok(None)
+                }
+                Peer::Receiver(receiver) => receiver.on_frame(&frame.data, now),
+                Peer::Sender(sender) => sender.on_frame(&frame.data, now).map(|_| None),
+            };
+
+            self.answer(answer);
+
+            Ok(None)
+        }
+
+        fn receive(&mut self) -> nb::Result<TestFrame, TestError> {
+            if let Peer::Sender(sender) = &mut self.peer {
+                let answer = sender.poll(self.clock.now());
+
+                self.answer(answer);
+            }
+
+            self.rx.pop_front().ok_or(nb::Error::WouldBlock)
+        }
+    }
+
+    fn id(raw: u16) -> Id {
+        StandardId::new(raw).unwrap().into()
+    }
+
+    fn configs() -> (Config, Config) {
+        (
+            Config::new(id(0x7E0), id(0x7E8)),
+            Config::new(id(0x7E8), id(0x7E0)),
+        )
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    /// Sends `data` to a peer receiving into a buffer of `buffer_len` bytes,
+    /// and returns the outcome on both ends
+    fn send_to_peer(
+        clock: &TestClock,
+        data: &[u8],
+        config: Config,
+        peer_config: Config,
+        buffer_len: usize,
+    ) -> (TestResult<()>, Result<Vec<u8>, ProtocolError>) {
+        let mut buffer = vec![0; buffer_len];
+
+        let receiver = Receiver::new(&mut buffer, &peer_config);
+        let can = TestCan::new(Peer::Receiver(receiver), &peer_config, clock);
+
+        let mut isotp = IsoTp::new(can, clock.clone(), config);
+        let sent = isotp.send(data);
+
+        let (can, _) = isotp.release();
+        let received = match (can.peer_error, can.peer) {
+            (Some(e), _) => Err(e),
+            (None, Peer::Receiver(mut receiver)) => receiver.poll(clock.now()),
+            _ => unreachable!(),
+        };
+
+        let received = received.map(|len| buffer[..len.expect("incomplete payload")].to_vec());
+
+        (sent, received)
+    }
+
+    /// Receives `data` from a peer into a buffer of `buffer_len` bytes, and
+    /// returns the outcome on both ends
+    fn receive_from_peer(
+        clock: &TestClock,
+        data: &[u8],
+        config: Config,
+        peer_config: Config,
+        buffer_len: usize,
+    ) -> (Result<(), ProtocolError>, TestResult<Vec<u8>>) {
+        let sender = Sender::new(data, &peer_config).unwrap();
+        let can = TestCan::new(Peer::Sender(sender), &peer_config, clock);
+
+        let mut buffer = vec![0; buffer_len];
+
+        let mut isotp = IsoTp::new(can, clock.clone(), config);
+        let received = isotp
+            .receive(&mut buffer, Some(Duration::from_secs(1)))
+            .map(|len| buffer[..len].to_vec());
+
+        let (can, _) = isotp.release();
+        let sent = match (can.peer_error, can.peer) {
+            (Some(e), _) => Err(e),
+            (None, Peer::Sender(sender)) => {
+                assert!(sender.is_done(), "incomplete payload");
+                Ok(())
+            }
+            _ => unreachable!(),
+        };
+
+        (sent, received)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (config, peer_config) = configs();
+
+        for len in [1, 7, 8, 13, 14, 111, 112, 500, MAX_PAYLOAD_LEN] {
+            let data = payload(len);
+
+            let (sent, received) = send_to_peer(
+                &TestClock::default(),
+                &data,
+                config,
+                peer_config,
+                MAX_PAYLOAD_LEN,
+            );
+
+            assert!(sent.is_ok(), "len {}: {:?}", len, sent);
+            assert_eq!(received, Ok(data.clone()), "len {}", len);
+
+            let (sent, received) = receive_from_peer(
+                &TestClock::default(),
+                &data,
+                config,
+                peer_config,
+                MAX_PAYLOAD_LEN,
+            );
+
+            assert_eq!(sent, Ok(()), "len {}", len);
+            assert_eq!(received.unwrap(), data, "len {}", len);
+        }
+    }
+
+    #[test]
+    fn round_trip_with_blocks() {
+        let (config, peer_config) = configs();
+        let st_min = Duration::from_micros(500);
+        let blocks = |config: Config| config.block_size(3).st_min(st_min).padding(None);
+
+        let data = payload(300);
+        // The first frame carries 6 bytes, every consecutive frame 7
+        let consecutive_frames = (300 - 6 + 6) / 7;
+
+        let clock = TestClock::default();
+        let (sent, received) = send_to_peer(&clock, &data, config, blocks(peer_config), 300);
+
+        assert!(sent.is_ok());
+        assert_eq!(received, Ok(data.clone()));
+        // The first consecutive frame of each block is sent right away
+        assert!(clock.now() >= st_min * (consecutive_frames - consecutive_frames / 3) as u32);
+
+        let clock = TestClock::default();
+        let (sent, received) = receive_from_peer(&clock, &data, blocks(config), peer_config, 300);
+
+        assert_eq!(sent, Ok(()));
+        assert_eq!(received.unwrap(), data);
+        assert!(clock.now() >= st_min * (consecutive_frames - consecutive_frames / 3) as u32);
+    }
+
+    #[test]
+    fn round_trip_overflow() {
+        let (config, peer_config) = configs();
+        let data = payload(100);
+
+        let (sent, received) = send_to_peer(&TestClock::default(), &data, config, peer_config, 99);
+
+        assert!(matches!(
+            sent,
+            Err(Error::Protocol(ProtocolError::Overflow))
+        ));
+        assert_eq!(received, Err(ProtocolError::Overflow));
+
+        let (sent, received) =
+            receive_from_peer(&TestClock::default(), &data, config, peer_config, 99);
+
+        assert_eq!(sent, Err(ProtocolError::Overflow));
+        assert!(matches!(
+            received,
+            Err(Error::Protocol(ProtocolError::Overflow))
+        ));
+    }
+
+    #[test]
+    fn send_times_out_without_flow_control() {
+        let (config, peer_config) = configs();
+        let config = config.timeout(Duration::from_millis(20));
+
+        let clock = TestClock::default();
+        let can = TestCan::new(Peer::Silent, &peer_config, &clock);
+        let mut isotp = IsoTp::new(can, clock.clone(), config);
+
+        assert!(matches!(
+            isotp.send(&payload(20)),
+            Err(Error::Protocol(ProtocolError::Timeout))
+        ));
+        assert_eq!(clock.now(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn transmit_times_out_when_the_bus_is_stuck() {
+        let (config, peer_config) = configs();
+        let config = config.timeout(Duration::from_millis(20));
+
+        let clock = TestClock::default();
+        let can = TestCan::new(Peer::Silent, &peer_config, &clock);
+        let mut isotp = IsoTp::new(can, clock.clone(), config);
+
+        // Fills the bus, which the peer does not drain
+        for _ in 0..TestCan::CAPACITY {
+            isotp.send(&[1]).unwrap();
+        }
+
+        assert_eq!(clock.now(), Duration::from_secs(0));
+        assert!(matches!(
+            isotp.send(&[1]),
+            Err(Error::Protocol(ProtocolError::Timeout))
+        ));
+        assert_eq!(clock.now(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn receive_times_out_without_frames() {
+        let (config, peer_config) = configs();
+
+        let clock = TestClock::default();
+        let can = TestCan::new(Peer::Silent, &peer_config, &clock);
+        let mut isotp = IsoTp::new(can, clock.clone(), config);
+
+        assert!(matches!(
+            isotp.receive(&mut [0; 8], Some(Duration::from_millis(20))),
+            Err(Error::Protocol(ProtocolError::Timeout))
+        ));
+        assert_eq!(clock.now(), Duration::from_millis(20));
+    }
+
+    /// Collects all frames of a transmission, acknowledging the first frame
+    /// with `flow_control`
+    fn segment(data: &[u8], config: &Config, flow_control: &[u8]) -> Vec<FrameData> {
+        let mut sender = Sender::new(data, config).unwrap();
+        let mut frames = Vec::new();
+
+        while !sender.is_done() {
+            match sender.poll(Duration::from_secs(0)).unwrap() {
+                Some(frame) => frames.push(frame),
+                None => sender
+                    .on_frame(flow_control, Duration::from_secs(0))
+                    .unwrap(),
+            }
+        }
+
+        frames
+    }
+
+    #[test]
+    fn single_frame() {
+        let config = configs().0;
+
+        let frames = segment(&[1, 2, 3], &config, &[]);
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            frames[0].as_slice(),
+            &[0x03, 1, 2, 3, 0xCC, 0xCC, 0xCC, 0xCC]
+        );
+
+        let frames = segment(&[1, 2, 3], &config.padding(None), &[]);
+
+        assert_eq!(frames[0].as_slice(), &[0x03, 1, 2, 3]);
+    }
+
+    #[test]
+    fn segmentation() {
+        let config = configs().0.padding(None);
+        let data = payload(0x123);
+
+        let frames = segment(&data, &config, &[0x30, 0, 0]);
+
+        // First frame with 6 bytes, then 7 bytes per consecutive frame
+        assert_eq!(frames.len(), 1 + (0x123 - 6 + 6) / 7);
+        assert_eq!(&frames[0].as_slice()[..2], &[0x11, 0x23]);
+        assert_eq!(&frames[0].as_slice()[2..], &data[..6]);
+
+        for (index, frame) in frames[1..].iter().enumerate() {
+            // The sequence number wraps around from 15 to 0
+            assert_eq!(frame.as_slice()[0], 0x20 | ((index + 1) & 0x0F) as u8);
+        }
+
+        let reassembled = frames
+            .iter()
+            .enumerate()
+            .flat_map(|(index, frame)| frame.as_slice()[if index == 0 { 2 } else { 1 }..].to_vec())
+            .collect::<Vec<_>>();
+
+        assert_eq!(reassembled, data);
+    }
+
+    #[test]
+    fn reassembly() {
+        let config = configs().1.block_size(2);
+        let data = payload(40);
+        let frames = segment(&data, &configs().0, &[0x30, 0, 0]);
+
+        let mut buffer = [0; 64];
+        let mut receiver = Receiver::new(&mut buffer, &config);
+        let mut flow_controls = Vec::new();
+
+        for frame in &frames {
+            if let Some(flow_control) = receiver
+                .on_frame(frame.as_slice(), Duration::from_secs(0))
+                .unwrap()
+            {
+                flow_controls.push(flow_control);
+            }
+        }
+
+        assert_eq!(receiver.poll(Duration::from_secs(0)), Ok(Some(40)));
+        // After the first frame and after every block of 2 of the 5
+        // consecutive frames, except for the last one
+        assert_eq!(flow_controls.len(), 3);
+        assert_eq!(&flow_controls[0].as_slice()[..3], &[0x30, 2, 0]);
+        assert_eq!(&buffer[..40], &data[..]);
+    }
+
+    #[test]
+    fn reassembly_rejects_wrong_sequence() {
+        let config = configs().1;
+        let frames = segment(&payload(40), &configs().0, &[0x30, 0, 0]);
+
+        let mut buffer = [0; 64];
+        let mut receiver = Receiver::new(&mut buffer, &config);
+
+        receiver
+            .on_frame(frames[0].as_slice(), Duration::from_secs(0))
+            .unwrap();
+
+        assert_eq!(
+            receiver.on_frame(frames[2].as_slice(), Duration::from_secs(0)),
+            Err(ProtocolError::InvalidSequence)
+        );
+    }
+
+    #[test]
+    fn reassembly_times_out() {
+        let config = configs().1;
+        let frames = segment(&payload(40), &configs().0, &[0x30, 0, 0]);
+
+        let mut buffer = [0; 64];
+        let mut receiver = Receiver::new(&mut buffer, &config);
+
+        receiver
+            .on_frame(frames[0].as_slice(), Duration::from_secs(0))
+            .unwrap();
+
+        assert_eq!(receiver.poll(config.timeout / 2), Ok(None));
+        assert_eq!(receiver.poll(config.timeout), Err(ProtocolError::Timeout));
+    }
+
+    #[test]
+    fn wait_frames_are_limited() {
+        let config = configs().0.max_wait_frames(2);
+        let now = Duration::from_secs(0);
+
+        let mut sender = Sender::new(&[0; 20], &config).unwrap();
+        sender.poll(now).unwrap().unwrap();
+
+        assert_eq!(sender.on_frame(&[0x31, 0, 0], now), Ok(()));
+        assert_eq!(sender.on_frame(&[0x31, 0, 0], now), Ok(()));
+        assert_eq!(
+            sender.on_frame(&[0x31, 0, 0], now),
+            Err(ProtocolError::WaitLimit)
+        );
+    }
+
+    #[test]
+    fn wait_frame_count_resets_on_continue() {
+        let config = configs().0.max_wait_frames(1);
+        let now = Duration::from_secs(0);
+
+        let mut sender = Sender::new(&[0; 30], &config).unwrap();
+        sender.poll(now).unwrap().unwrap();
+
+        assert_eq!(sender.on_frame(&[0x31, 0, 0], now), Ok(()));
+        // Continue with a block size of 1
+        assert_eq!(sender.on_frame(&[0x30, 1, 0], now), Ok(()));
+        sender.poll(now).unwrap().unwrap();
+
+        assert_eq!(sender.on_frame(&[0x31, 0, 0], now), Ok(()));
+        assert_eq!(
+            sender.on_frame(&[0x31, 0, 0], now),
+            Err(ProtocolError::WaitLimit)
+        );
+    }
+
+    #[test]
+    fn st_min_encoding() {
+        assert_eq!(encode_st_min(Duration::from_millis(0)), 0);
+        assert_eq!(encode_st_min(Duration::from_micros(100)), 0xF1);
+        assert_eq!(encode_st_min(Duration::from_micros(150)), 0xF2);
+        assert_eq!(encode_st_min(Duration::from_micros(901)), 1);
+        assert_eq!(encode_st_min(Duration::from_millis(500)), 0x7F);
+
+        assert_eq!(decode_st_min(0x05), Duration::from_millis(5));
+        assert_eq!(decode_st_min(0xF9), Duration::from_micros(900));
+        assert_eq!(decode_st_min(0x80), Duration::from_millis(127));
+    }
+}
@@ -0,0 +1,1403 @@
+//! SAE J1939 protocol support on top of CAN.
+//!
+//! Provides the mapping between 29-bit CAN IDs and J1939 identifiers
+//! (priority, PGN, source and destination address), the address claiming
+//! procedure of J1939-81 and the BAM and RTS/CTS transport protocols of
+//! J1939-21 for messages longer than 8 bytes.
+//!
+//! Like the [`crate::isotp`] module, the state machines work on [`Packet`]s
+//! and are handed the current time by the caller, so that they can be driven
+//! by any CAN driver (and tested off-target). [`Packet::from_frame`] and
+//! [`Packet::to_frame`] convert from and to anything implementing the
+//! `embedded-hal` `Frame` trait, e.g. [`crate::can::Frame`].
+//!
+//! # Example
+//!
+//! ```no_run
+//! use embedded_hal::can::nb::Can;
+//! use esp_idf_hal::can;
+//! use esp_idf_hal::isotp::{Clock, EspClock};
+//! use esp_idf_hal::j1939;
+//! use esp_idf_hal::peripherals::Peripherals;
+//!
+//! let peripherals = Peripherals::take().unwrap();
+//! let mut can = can::CanBus::new(
+//!     peripherals.can,
+//!     peripherals.pins.gpio5,
+//!     peripherals.pins.gpio4,
+//!     can::config::Config::new()
+//!         .timing(can::config::Timing::B250K)
+//!         .filter(can::config::Filter::extended_allow_all()),
+//! )
+//! .unwrap();
+//!
+//! let name = j1939::Name::new(0x8000_0000_0012_3456);
+//! let mut claimer = j1939::AddressClaimer::new(name, 0x80);
+//!
+//! let claim: can::Frame = claimer.start(EspClock.now()).to_frame().unwrap();
+//! nb::block!(can.transmit(&claim)).unwrap();
+//!
+//! let address = loop {
+//!     if let Some(address) = claimer.poll(EspClock.now()) {
+//!         break Some(address);
+//!     }
+//!
+//!     if claimer.cannot_claim() {
+//!         break None;
+//!     }
+//!
+//!     match can.receive() {
+//!         Ok(frame) => {
+//!             if let Some(packet) = j1939::Packet::from_frame(&frame) {
+//!                 if let Some(reply) = claimer.on_packet(&packet, EspClock.now()) {
+//!                     nb::block!(can.transmit(&reply.to_frame::<can::Frame>().unwrap())).unwrap();
+//!                 }
+//!             }
+//!         }
+//!         // Nothing received yet, let the other tasks run
+//!         Err(nb::Error::WouldBlock) => EspClock.idle(),
+//!         Err(nb::Error::Other(e)) => panic!("CAN error: {:?}", e),
+//!     }
+//! };
+//!
+//! match address {
+//!     Some(address) => println!("Claimed address {}", address),
+//!     // Lost the contention for every address in range: the node must not
+//!     // send anything but the "cannot claim" answers of `on_packet`
+//!     None => println!("Cannot claim an address"),
+//! }
+//! ```
+
+use core::time::Duration;
+
+use embedded_hal::can::{ExtendedId, Id};
+
+/// Destination address of broadcast messages
+pub const GLOBAL_ADDRESS: u8 = 0xFF;
+/// Source address of nodes which could not claim an address
+pub const NULL_ADDRESS: u8 = 0xFE;
+
+pub const PGN_REQUEST: u32 = 0xEA00;
+pub const PGN_ADDRESS_CLAIMED: u32 = 0xEE00;
+pub const PGN_TP_CM: u32 = 0xEC00;
+pub const PGN_TP_DT: u32 = 0xEB00;
+
+/// Largest message which can be sent with the transport protocols
+pub const MAX_TRANSPORT_LEN: usize = 255 * 7;
+
+const DEFAULT_PRIORITY: u8 = 6;
+const TRANSPORT_PRIORITY: u8 = 7;
+
+const CLAIM_TIMEOUT: Duration = Duration::from_millis(250);
+
+const BAM_INTERVAL: Duration = Duration::from_millis(50);
+const T1: Duration = Duration::from_millis(750);
+const T2: Duration = Duration::from_millis(1250);
+const T3: Duration = Duration::from_millis(1250);
+const T4: Duration = Duration::from_millis(1050);
+
+const TP_CM_RTS: u8 = 16;
+const TP_CM_CTS: u8 = 17;
+const TP_CM_END_OF_MESSAGE_ACK: u8 = 19;
+const TP_CM_BAM: u8 = 32;
+const TP_CM_ABORT: u8 = 255;
+
+/// Abort reason: already in one or more connection managed sessions
+pub const ABORT_BUSY: u8 = 1;
+/// Abort reason: system resources were needed for another task
+pub const ABORT_RESOURCES: u8 = 2;
+/// Abort reason: a timeout occurred
+pub const ABORT_TIMEOUT: u8 = 3;
+
+/// J1939 view of a 29-bit CAN ID
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Identifier {
+    pub priority: u8,
+    /// Parameter group number; for PDU1 (peer-to-peer) PGNs the PDU specific
+    /// byte is always zero, the destination is kept in `destination` instead
+    pub pgn: u32,
+    pub source: u8,
+    /// Destination address; [`GLOBAL_ADDRESS`] for PDU2 (broadcast) PGNs
+    pub destination: u8,
+}
+
+impl Identifier {
+    pub fn new(priority: u8, pgn: u32, source: u8, destination: u8) -> Self {
+        Self {
+            priority,
+            pgn: if is_pdu1(pgn) { pgn & 0x3FF00 } else { pgn },
+            source,
+            destination: if is_pdu1(pgn) {
+                destination
+            } else {
+                GLOBAL_ADDRESS
+            },
+        }
+    }
+
+    pub fn from_raw(raw: u32) -> Self {
+        let pgn = (raw >> 8) & 0x3FFFF;
+
+        Self::new(((raw >> 26) & 0x7) as u8, pgn, raw as u8, pgn as u8)
+    }
+
+    pub fn raw(&self) -> u32 {
+        let pgn = if is_pdu1(self.pgn) {
+            (self.pgn & 0x3FF00) | self.destination as u32
+        } else {
+            self.pgn & 0x3FFFF
+        };
+
+        ((self.priority as u32 & 0x7) << 26) | (pgn << 8) | self.source as u32
+    }
+
+    /// Whether the PGN is peer-to-peer, i.e. carries a destination address
+    pub fn is_pdu1(&self) -> bool {
+        is_pdu1(self.pgn)
+    }
+}
+
+impl From<ExtendedId> for Identifier {
+    fn from(id: ExtendedId) -> Self {
+        Self::from_raw(id.as_raw())
+    }
+}
+
+impl From<Identifier> for ExtendedId {
+    fn from(id: Identifier) -> Self {
+        // `raw` never exceeds 29 bits
+        ExtendedId::new(id.raw()).unwrap()
+    }
+}
+
+fn is_pdu1(pgn: u32) -> bool {
+    (pgn >> 8) & 0xFF < 240
+}
+
+/// J1939 message of up to 8 bytes, i.e. the payload of a single CAN frame
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Packet {
+    pub id: Identifier,
+    data: [u8; 8],
+    len: usize,
+}
+
+impl Packet {
+    pub fn new(id: Identifier, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+
+        let mut packet = Self {
+            id,
+            data: [0xFF; 8],
+            len: data.len(),
+        };
+        packet.data[..data.len()].copy_from_slice(data);
+
+        Some(packet)
+    }
+
+    /// Request for `pgn`, sent from `source` to `destination`
+    pub fn request(pgn: u32, source: u8, destination: u8) -> Self {
+        Self::new(
+            Identifier::new(DEFAULT_PRIORITY, PGN_REQUEST, source, destination),
+            &[pgn as u8, (pgn >> 8) as u8, (pgn >> 16) as u8],
+        )
+        .unwrap()
+    }
+
+    /// Converts an extended data frame; returns `None` for any other frame
+    pub fn from_frame<F: embedded_hal::can::Frame>(frame: &F) -> Option<Self> {
+        match frame.id() {
+            Id::Extended(id) if frame.is_data_frame() => Self::new(id.into(), frame.data()),
+            _ => None,
+        }
+    }
+
+    pub fn to_frame<F: embedded_hal::can::Frame>(&self) -> Option<F> {
+        F::new(ExtendedId::from(self.id), self.data())
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    fn transport(source: u8, destination: u8, pgn: u32, data: [u8; 8]) -> Self {
+        Self {
+            id: Identifier::new(TRANSPORT_PRIORITY, pgn, source, destination),
+            data,
+            len: 8,
+        }
+    }
+
+    fn control(source: u8, destination: u8, data: [u8; 5], pgn: u32) -> Self {
+        Self::transport(
+            source,
+            destination,
+            PGN_TP_CM,
+            [
+                data[0],
+                data[1],
+                data[2],
+                data[3],
+                data[4],
+                pgn as u8,
+                (pgn >> 8) as u8,
+                (pgn >> 16) as u8,
+            ],
+        )
+    }
+
+    fn abort(source: u8, destination: u8, reason: u8, pgn: u32) -> Self {
+        Self::control(
+            source,
+            destination,
+            [TP_CM_ABORT, reason, 0xFF, 0xFF, 0xFF],
+            pgn,
+        )
+    }
+
+    fn control_pgn(&self) -> u32 {
+        self.data[5] as u32 | (self.data[6] as u32) << 8 | (self.data[7] as u32) << 16
+    }
+}
+
+/// 64-bit J1939 NAME, which uniquely identifies a node and decides address
+/// claiming contentions (the lower NAME wins)
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Name(pub u64);
+
+impl Name {
+    pub fn new(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    pub fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self(u64::from_le_bytes(bytes))
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn identity_number(&self) -> u32 {
+        (self.0 & 0x1F_FFFF) as u32
+    }
+
+    pub fn manufacturer_code(&self) -> u16 {
+        ((self.0 >> 21) & 0x7FF) as u16
+    }
+
+    pub fn ecu_instance(&self) -> u8 {
+        ((self.0 >> 32) & 0x7) as u8
+    }
+
+    pub fn function_instance(&self) -> u8 {
+        ((self.0 >> 35) & 0x1F) as u8
+    }
+
+    pub fn function(&self) -> u8 {
+        (self.0 >> 40) as u8
+    }
+
+    pub fn vehicle_system(&self) -> u8 {
+        ((self.0 >> 49) & 0x7F) as u8
+    }
+
+    pub fn vehicle_system_instance(&self) -> u8 {
+        ((self.0 >> 56) & 0xF) as u8
+    }
+
+    pub fn industry_group(&self) -> u8 {
+        ((self.0 >> 60) & 0x7) as u8
+    }
+
+    pub fn arbitrary_address_capable(&self) -> bool {
+        self.0 >> 63 != 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ClaimState {
+    Idle,
+    Claiming { since: Duration },
+    Claimed,
+    CannotClaim,
+}
+
+/// Address claiming procedure (J1939-81)
+///
+/// Nodes whose NAME is arbitrary address capable move to the next free
+/// address of the configured range when they lose a contention; other nodes
+/// give up and announce that they cannot claim an address.
+pub struct AddressClaimer {
+    name: Name,
+    address: u8,
+    first: u8,
+    last: u8,
+    attempts: u16,
+    state: ClaimState,
+}
+
+impl AddressClaimer {
+    pub fn new(name: Name, preferred_address: u8) -> Self {
+        Self {
+            name,
+            address: preferred_address,
+            first: 128,
+            last: 247,
+            attempts: 0,
+            state: ClaimState::Idle,
+        }
+    }
+
+    /// Range of addresses tried after losing a contention, by arbitrary
+    /// address capable nodes
+    #[must_use]
+    pub fn arbitrary_range(mut self, first: u8, last: u8) -> Self {
+        self.first = first.min(last);
+        self.last = first.max(last);
+        self
+    }
+
+    pub fn name(&self) -> Name {
+        self.name
+    }
+
+    /// Claimed address, once no other node contested it for 250 ms
+    pub fn address(&self) -> Option<u8> {
+        if self.state == ClaimState::Claimed {
+            Some(self.address)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the node gave up, in which case it must not send anything but
+    /// "cannot claim" messages
+    pub fn cannot_claim(&self) -> bool {
+        self.state == ClaimState::CannotClaim
+    }
+
+    /// Starts the procedure and returns the claim to send
+    pub fn start(&mut self, now: Duration) -> Packet {
+        self.attempts = 0;
+        self.state = ClaimState::Claiming { since: now };
+
+        self.claim()
+    }
+
+    /// Returns the claimed address once the claim has been held long enough
+    pub fn poll(&mut self, now: Duration) -> Option<u8> {
+        if let ClaimState::Claiming { since } = self.state {
+            if now >= since + CLAIM_TIMEOUT {
+                self.state = ClaimState::Claimed;
+            }
+        }
+
+        self.address()
+    }
+
+    /// Processes a packet received from the bus and returns the packet to
+    /// answer with, if any
+    pub fn on_packet(&mut self, packet: &Packet, now: Duration) -> Option<Packet> {
+        match packet.id.pgn {
+            PGN_REQUEST if packet.data().len() >= 3 => {
+                let pgn = packet.data[0] as u32
+                    | (packet.data[1] as u32) << 8
+                    | (packet.data[2] as u32) << 16;
+
+                if pgn != PGN_ADDRESS_CLAIMED
+                    || (packet.id.destination != GLOBAL_ADDRESS
+                        && packet.id.destination != self.address)
+                {
+                    return None;
+                }
+
+                match self.state {
+                    ClaimState::Idle => None,
+                    _ => Some(self.claim()),
+                }
+            }
+            PGN_ADDRESS_CLAIMED if packet.data().len() == 8 => {
+                if packet.id.source != self.address
+                    || !matches!(
+                        self.state,
+                        ClaimState::Claiming { .. } | ClaimState::Claimed
+                    )
+                {
+                    return None;
+                }
+
+                let other = Name::from_bytes(packet.data);
+
+                if other == self.name {
+                    // Our own claim, echoed back
+                    None
+                } else if self.name < other {
+                    Some(self.claim())
+                } else {
+                    self.lose(now);
+
+                    Some(self.claim())
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn lose(&mut self, now: Duration) {
+        let range = self.last as u16 - self.first as u16 + 1;
+
+        if !self.name.arbitrary_address_capable() || self.attempts >= range {
+            self.state = ClaimState::CannotClaim;
+            return;
+        }
+
+        self.attempts += 1;
+        self.address = if self.address < self.first || self.address >= self.last {
+            self.first
+        } else {
+            self.address + 1
+        };
+
+        self.state = ClaimState::Claiming { since: now };
+    }
+
+    fn claim(&self) -> Packet {
+        let source = if self.state == ClaimState::CannotClaim {
+            NULL_ADDRESS
+        } else {
+            self.address
+        };
+
+        Packet::new(
+            Identifier::new(
+                DEFAULT_PRIORITY,
+                PGN_ADDRESS_CLAIMED,
+                source,
+                GLOBAL_ADDRESS,
+            ),
+            &self.name.to_bytes(),
+        )
+        .unwrap()
+    }
+}
+
+/// Failure of a multi-packet transfer
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TransportError {
+    /// The peer did not answer or send the next packet in time
+    Timeout,
+    /// The peer aborted the transfer with the given reason
+    Aborted(u8),
+    /// The message is shorter than 9 or longer than [`MAX_TRANSPORT_LEN`] bytes
+    InvalidLength,
+    /// A data packet was received or requested out of order
+    InvalidSequence,
+    /// The message does not fit into the receive buffer
+    Overflow,
+}
+
+impl core::fmt::Display for TransportError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TransportError {}
+
+#[derive(Debug, Copy, Clone)]
+enum SenderState {
+    Start,
+    Broadcast { next_at: Duration },
+    WaitClearToSend { deadline: Duration },
+    Sending { last: u8 },
+    WaitEndOfMessage { deadline: Duration },
+    Done,
+}
+
+/// Sending side of the transport protocols
+///
+/// Messages to [`GLOBAL_ADDRESS`] are broadcast with BAM, messages to a
+/// specific node use the RTS/CTS handshake.
+pub struct TransportSender<'a> {
+    pgn: u32,
+    data: &'a [u8],
+    source: u8,
+    destination: u8,
+    sequence: u8,
+    state: SenderState,
+}
+
+impl<'a> TransportSender<'a> {
+    pub fn new(
+        pgn: u32,
+        data: &'a [u8],
+        source: u8,
+        destination: u8,
+    ) -> Result<Self, TransportError> {
+        if data.len() <= 8 || data.len() > MAX_TRANSPORT_LEN {
+            return Err(TransportError::InvalidLength);
+        }
+
+        Ok(Self {
+            pgn,
+            data,
+            source,
+            destination,
+            sequence: 1,
+            state: SenderState::Start,
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, SenderState::Done)
+    }
+
+    /// Returns the next packet to send at time `now`, if any is due
+    pub fn poll(&mut self, now: Duration) -> Result<Option<Packet>, TransportError> {
+        match self.state {
+            SenderState::Start if self.destination == GLOBAL_ADDRESS => {
+                self.state = SenderState::Broadcast {
+                    next_at: now + BAM_INTERVAL,
+                };
+
+                Ok(Some(self.announce(TP_CM_BAM, 0xFF)))
+            }
+            SenderState::Start => {
+                self.state = SenderState::WaitClearToSend { deadline: now + T3 };
+
+                Ok(Some(self.announce(TP_CM_RTS, 0xFF)))
+            }
+            SenderState::Broadcast { next_at } => {
+                if now < next_at {
+                    return Ok(None);
+                }
+
+                let packet = self.data_packet();
+
+                self.state = if self.sequence == self.packets() {
+                    SenderState::Done
+                } else {
+                    SenderState::Broadcast {
+                        next_at: now + BAM_INTERVAL,
+                    }
+                };
+                self.sequence = self.sequence.wrapping_add(1);
+
+                Ok(Some(packet))
+            }
+            SenderState::WaitClearToSend { deadline }
+            | SenderState::WaitEndOfMessage { deadline } => {
+                if now >= deadline {
+                    self.state = SenderState::Done;
+
+                    Err(TransportError::Timeout)
+                } else {
+                    Ok(None)
+                }
+            }
+            SenderState::Sending { last } => {
+                let packet = self.data_packet();
+
+                if self.sequence == last {
+                    self.state = if last == self.packets() {
+                        SenderState::WaitEndOfMessage { deadline: now + T3 }
+                    } else {
+                        SenderState::WaitClearToSend { deadline: now + T3 }
+                    };
+                }
+                self.sequence = self.sequence.wrapping_add(1);
+
+                Ok(Some(packet))
+            }
+            SenderState::Done => Ok(None),
+        }
+    }
+
+    /// Processes a packet received from the bus
+    pub fn on_packet(&mut self, packet: &Packet, now: Duration) -> Result<(), TransportError> {
+        if packet.id.pgn != PGN_TP_CM
+            || packet.id.source != self.destination
+            || packet.id.destination != self.source
+            || packet.len != 8
+            || packet.control_pgn() != self.pgn
+        {
+            return Ok(());
+        }
+
+        match (packet.data[0], self.state) {
+            (TP_CM_CTS, SenderState::WaitClearToSend { .. })
+            | (TP_CM_CTS, SenderState::WaitEndOfMessage { .. }) => {
+                let count = packet.data[1];
+                let next = packet.data[2];
+
+                if count == 0 {
+                    self.state = SenderState::WaitClearToSend { deadline: now + T4 };
+                    return Ok(());
+                }
+
+                if next == 0 || next > self.packets() {
+                    self.state = SenderState::Done;
+                    return Err(TransportError::InvalidSequence);
+                }
+
+                self.sequence = next;
+                self.state = SenderState::Sending {
+                    last: (next as u16 + count as u16 - 1).min(self.packets() as u16) as u8,
+                };
+
+                Ok(())
+            }
+            (TP_CM_END_OF_MESSAGE_ACK, SenderState::WaitEndOfMessage { .. }) => {
+                self.state = SenderState::Done;
+
+                Ok(())
+            }
+            (TP_CM_ABORT, _) => {
+                self.state = SenderState::Done;
+
+                Err(TransportError::Aborted(packet.data[1]))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn packets(&self) -> u8 {
+        ((self.data.len() + 6) / 7) as u8
+    }
+
+    fn announce(&self, control: u8, max_packets: u8) -> Packet {
+        let len = self.data.len();
+
+        Packet::control(
+            self.source,
+            self.destination,
+            [
+                control,
+                len as u8,
+                (len >> 8) as u8,
+                self.packets(),
+                max_packets,
+            ],
+            self.pgn,
+        )
+    }
+
+    fn data_packet(&self) -> Packet {
+        let offset = (self.sequence as usize - 1) * 7;
+        let end = (offset + 7).min(self.data.len());
+
+        let mut data = [0xFF; 8];
+        data[0] = self.sequence;
+        data[1..1 + end - offset].copy_from_slice(&self.data[offset..end]);
+
+        Packet::transport(self.source, self.destination, PGN_TP_DT, data)
+    }
+}
+
+/// Multi-packet message received through the transport protocols
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Message {
+    pub pgn: u32,
+    pub source: u8,
+    pub destination: u8,
+    pub len: usize,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Session {
+    message: Message,
+    packets: u8,
+    next: u8,
+    max_packets: u8,
+    window_end: u8,
+    deadline: Duration,
+}
+
+/// Receiving side of the transport protocols
+///
+/// Handles one transfer at a time; RTS from other nodes are rejected while a
+/// transfer is in progress.
+pub struct TransportReceiver<'a> {
+    buffer: &'a mut [u8],
+    address: u8,
+    session: Option<Session>,
+    completed: Option<Message>,
+}
+
+impl<'a> TransportReceiver<'a> {
+    pub fn new(buffer: &'a mut [u8], address: u8) -> Self {
+        Self {
+            buffer,
+            address,
+            session: None,
+            completed: None,
+        }
+    }
+
+    /// Payload of the last completed message
+    pub fn data(&self, message: &Message) -> &[u8] {
+        &self.buffer[..message.len]
+    }
+
+    /// Processes a packet received from the bus and returns the packet to
+    /// answer with, if any
+    pub fn on_packet(
+        &mut self,
+        packet: &Packet,
+        now: Duration,
+    ) -> Result<Option<Packet>, TransportError> {
+        if packet.len != 8
+            || (packet.id.destination != self.address && packet.id.destination != GLOBAL_ADDRESS)
+        {
+            return Ok(None);
+        }
+
+        match packet.id.pgn {
+            PGN_TP_CM => self.on_control(packet, now),
+            PGN_TP_DT => self.on_data(packet, now),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the message once it has been completely received
+    pub fn poll(&mut self, now: Duration) -> Result<Option<Message>, TransportError> {
+        if let Some(session) = self.session {
+            if now >= session.deadline {
+                self.session = None;
+
+                return Err(TransportError::Timeout);
+            }
+        }
+
+        Ok(self.completed.take())
+    }
+
+    fn on_control(
+        &mut self,
+        packet: &Packet,
+        now: Duration,
+    ) -> Result<Option<Packet>, TransportError> {
+        let source = packet.id.source;
+        let pgn = packet.control_pgn();
+        let broadcast = packet.id.destination == GLOBAL_ADDRESS;
+
+        match packet.data[0] {
+            TP_CM_BAM if broadcast => {
+                let session = Self::session(packet, now + T1)?;
+
+                if session.message.len > self.buffer.len() {
+                    return Err(TransportError::Overflow);
+                }
+
+                self.session = Some(session);
+
+                Ok(None)
+            }
+            TP_CM_RTS if !broadcast => {
+                let address = self.address;
+                let reject = |reason| Packet::abort(address, source, reason, pgn);
+
+                match self.session {
+                    Some(session) if session.message.source != source => {
+                        return Ok(Some(reject(ABORT_BUSY)))
+                    }
+                    _ => (),
+                }
+
+                let mut session = match Self::session(packet, now + T2) {
+                    Ok(session) => session,
+                    Err(_) => return Ok(Some(reject(ABORT_RESOURCES))),
+                };
+
+                if session.message.len > self.buffer.len() {
+                    return Ok(Some(reject(ABORT_RESOURCES)));
+                }
+
+                session.window_end = session.max_packets.min(session.packets);
+                self.session = Some(session);
+
+                Ok(Some(self.clear_to_send(&session)))
+            }
+            TP_CM_ABORT => match self.session {
+                Some(session) if session.message.source == source && session.message.pgn == pgn => {
+                    self.session = None;
+
+                    Err(TransportError::Aborted(packet.data[1]))
+                }
+                _ => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn on_data(
+        &mut self,
+        packet: &Packet,
+        now: Duration,
+    ) -> Result<Option<Packet>, TransportError> {
+        let mut session = match self.session {
+            Some(session)
+                if session.message.source == packet.id.source
+                    && session.message.destination == packet.id.destination =>
+            {
+                session
+            }
+            _ => return Ok(None),
+        };
+
+        let sequence = packet.data[0];
+        if sequence != session.next {
+            self.session = None;
+
+            return Err(TransportError::InvalidSequence);
+        }
+
+        let offset = (sequence as usize - 1) * 7;
+        let count = (session.message.len - offset).min(7);
+        self.buffer[offset..offset + count].copy_from_slice(&packet.data[1..1 + count]);
+
+        let broadcast = session.message.destination == GLOBAL_ADDRESS;
+
+        if sequence == session.packets {
+            self.session = None;
+            self.completed = Some(session.message);
+
+            return Ok(if broadcast {
+                None
+            } else {
+                Some(Packet::control(
+                    self.address,
+                    session.message.source,
+                    [
+                        TP_CM_END_OF_MESSAGE_ACK,
+                        session.message.len as u8,
+                        (session.message.len >> 8) as u8,
+                        session.packets,
+                        0xFF,
+                    ],
+                    session.message.pgn,
+                ))
+            });
+        }
+
+        session.next += 1;
+
+        let reply = if !broadcast && sequence == session.window_end {
+            session.window_end =
+                (sequence as u16 + session.max_packets as u16).min(session.packets as u16) as u8;
+            session.deadline = now + T2;
+
+            Some(self.clear_to_send(&session))
+        } else {
+            session.deadline = now + T1;
+
+            None
+        };
+
+        self.session = Some(session);
+
+        Ok(reply)
+    }
+
+    fn session(packet: &Packet, deadline: Duration) -> Result<Session, TransportError> {
+        let len = packet.data[1] as usize | (packet.data[2] as usize) << 8;
+        let packets = packet.data[3];
+
+        if len <= 8 || len > MAX_TRANSPORT_LEN || packets as usize != (len + 6) / 7 {
+            return Err(TransportError::InvalidLength);
+        }
+
+        Ok(Session {
+            message: Message {
+                pgn: packet.control_pgn(),
+                source: packet.id.source,
+                destination: packet.id.destination,
+                len,
+            },
+            packets,
+            next: 1,
+            max_packets: if packet.data[4] == 0 {
+                0xFF
+            } else {
+                packet.data[4]
+            },
+            window_end: packets,
+            deadline,
+        })
+    }
+
+    fn clear_to_send(&self, session: &Session) -> Packet {
+        Packet::control(
+            self.address,
+            session.message.source,
+            [
+                TP_CM_CTS,
+                session.window_end - session.next + 1,
+                session.next,
+                0xFF,
+                0xFF,
+            ],
+            session.message.pgn,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn identifier_pdu2() {
+        // CCVS from the engine
+        let id = Identifier::from_raw(0x18FE_F100);
+
+        assert_eq!(
+            id,
+            Identifier {
+                priority: 6,
+                pgn: 0xFEF1,
+                source: 0x00,
+                destination: GLOBAL_ADDRESS,
+            }
+        );
+        assert!(!id.is_pdu1());
+        assert_eq!(id.raw(), 0x18FE_F100);
+    }
+
+    #[test]
+    fn identifier_pdu1() {
+        // Request from 0x80 to 0x20
+        let id = Identifier::from_raw(0x18EA_2080);
+
+        assert_eq!(
+            id,
+            Identifier {
+                priority: 6,
+                pgn: PGN_REQUEST,
+                source: 0x80,
+                destination: 0x20,
+            }
+        );
+        assert!(id.is_pdu1());
+        assert_eq!(id.raw(), 0x18EA_2080);
+    }
+
+    #[test]
+    fn identifier_data_page() {
+        let id = Identifier::from_raw(0x0DFE_F123);
+
+        assert_eq!(id.priority, 3);
+        assert_eq!(id.pgn, 0x1_FEF1);
+        assert_eq!(id.source, 0x23);
+        assert_eq!(id.raw(), 0x0DFE_F123);
+    }
+
+    #[test]
+    fn identifier_new_normalizes_pgn() {
+        // The PDU specific byte of a PDU1 PGN is the destination
+        let id = Identifier::new(6, 0xEA55, 0x01, 0x02);
+
+        assert_eq!(id.pgn, 0xEA00);
+        assert_eq!(id.destination, 0x02);
+        assert_eq!(id.raw(), 0x18EA_0201);
+
+        // PDU2 PGNs are always broadcast
+        let id = Identifier::new(6, 0xFEF1, 0x01, 0x02);
+
+        assert_eq!(id.destination, GLOBAL_ADDRESS);
+        assert_eq!(id.raw(), 0x18FE_F101);
+    }
+
+    #[test]
+    fn identifier_extended_id() {
+        let id = Identifier::new(7, PGN_TP_CM, 0x10, 0x20);
+
+        assert_eq!(ExtendedId::from(id).as_raw(), 0x1CEC_2010);
+        assert_eq!(Identifier::from(ExtendedId::from(id)), id);
+    }
+
+    fn claimed_by(name: Name, address: u8) -> Packet {
+        Packet::new(
+            Identifier::new(6, PGN_ADDRESS_CLAIMED, address, GLOBAL_ADDRESS),
+            &name.to_bytes(),
+        )
+        .unwrap()
+    }
+
+    const ARBITRARY: u64 = 1 << 63;
+
+    #[test]
+    fn claim_uncontested() {
+        let name = Name::new(0x1234);
+        let mut claimer = AddressClaimer::new(name, 0x80);
+
+        let claim = claimer.start(ms(0));
+
+        assert_eq!(claim, claimed_by(name, 0x80));
+        assert_eq!(claim.id.raw(), 0x18EE_FF80);
+        assert_eq!(claimer.poll(ms(249)), None);
+        assert_eq!(claimer.poll(ms(250)), Some(0x80));
+        assert_eq!(claimer.address(), Some(0x80));
+    }
+
+    #[test]
+    fn claim_ignores_own_echo() {
+        let name = Name::new(0x1234);
+        let mut claimer = AddressClaimer::new(name, 0x80);
+
+        let claim = claimer.start(ms(0));
+
+        assert_eq!(claimer.on_packet(&claim, ms(1)), None);
+        assert_eq!(claimer.poll(ms(250)), Some(0x80));
+    }
+
+    #[test]
+    fn claim_contention_won() {
+        let name = Name::new(0x1234);
+        let mut claimer = AddressClaimer::new(name, 0x80);
+
+        claimer.start(ms(0));
+
+        // The higher NAME loses and is told so by a repeated claim
+        assert_eq!(
+            claimer.on_packet(&claimed_by(Name::new(0x1235), 0x80), ms(100)),
+            Some(claimed_by(name, 0x80))
+        );
+        assert_eq!(claimer.poll(ms(250)), Some(0x80));
+
+        // Also after the address has been claimed
+        assert_eq!(
+            claimer.on_packet(&claimed_by(Name::new(0x1235), 0x80), ms(300)),
+            Some(claimed_by(name, 0x80))
+        );
+        assert_eq!(claimer.address(), Some(0x80));
+    }
+
+    #[test]
+    fn claim_contention_lost_moves_on() {
+        let name = Name::new(ARBITRARY | 0x1234);
+        let mut claimer = AddressClaimer::new(name, 0x20);
+
+        claimer.start(ms(0));
+
+        assert_eq!(
+            claimer.on_packet(&claimed_by(Name::new(0x1233), 0x20), ms(100)),
+            Some(claimed_by(name, 128))
+        );
+        // The claim of the new address has to be held for another 250 ms
+        assert_eq!(claimer.poll(ms(250)), None);
+        assert_eq!(claimer.poll(ms(350)), Some(128));
+    }
+
+    #[test]
+    fn claim_contention_lost_without_arbitrary_address() {
+        let name = Name::new(0x1234);
+        let mut claimer = AddressClaimer::new(name, 0x20);
+
+        claimer.start(ms(0));
+
+        assert_eq!(
+            claimer.on_packet(&claimed_by(Name::new(0x1233), 0x20), ms(100)),
+            Some(claimed_by(name, NULL_ADDRESS))
+        );
+        assert!(claimer.cannot_claim());
+        assert_eq!(claimer.poll(ms(1000)), None);
+    }
+
+    #[test]
+    fn claim_range_exhausted() {
+        let name = Name::new(ARBITRARY | 0x1234);
+        let other = Name::new(0x1233);
+        let mut claimer = AddressClaimer::new(name, 0x20).arbitrary_range(129, 128);
+
+        claimer.start(ms(0));
+
+        assert_eq!(
+            claimer.on_packet(&claimed_by(other, 0x20), ms(10)),
+            Some(claimed_by(name, 128))
+        );
+        assert_eq!(
+            claimer.on_packet(&claimed_by(other, 128), ms(20)),
+            Some(claimed_by(name, 129))
+        );
+        // Claims for addresses other than the one being claimed are ignored
+        assert_eq!(claimer.on_packet(&claimed_by(other, 128), ms(30)), None);
+        assert_eq!(
+            claimer.on_packet(&claimed_by(other, 129), ms(40)),
+            Some(claimed_by(name, NULL_ADDRESS))
+        );
+        assert!(claimer.cannot_claim());
+    }
+
+    #[test]
+    fn claim_answers_requests() {
+        let name = Name::new(0x1234);
+        let mut claimer = AddressClaimer::new(name, 0x80);
+
+        let request = Packet::request(PGN_ADDRESS_CLAIMED, 0x10, GLOBAL_ADDRESS);
+
+        // Not started yet
+        assert_eq!(claimer.on_packet(&request, ms(0)), None);
+
+        claimer.start(ms(0));
+        claimer.poll(ms(250));
+
+        assert_eq!(
+            claimer.on_packet(&request, ms(300)),
+            Some(claimed_by(name, 0x80))
+        );
+        assert_eq!(
+            claimer.on_packet(&Packet::request(PGN_ADDRESS_CLAIMED, 0x10, 0x80), ms(300)),
+            Some(claimed_by(name, 0x80))
+        );
+        // Requests for another node or PGN
+        assert_eq!(
+            claimer.on_packet(&Packet::request(PGN_ADDRESS_CLAIMED, 0x10, 0x81), ms(300)),
+            None
+        );
+        assert_eq!(
+            claimer.on_packet(&Packet::request(0xFEF1, 0x10, GLOBAL_ADDRESS), ms(300)),
+            None
+        );
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    /// Runs a transfer, handing every packet of the sender to the receiver
+    /// and every reply of the receiver back to the sender
+    ///
+    /// `tamper` may modify the packets of the sender in flight. Returns all
+    /// packets exchanged and the time at which the sender finished.
+    fn exchange(
+        sender: &mut TransportSender,
+        receiver: &mut TransportReceiver,
+        tamper: impl Fn(&mut Packet),
+    ) -> Result<(Vec<Packet>, Duration), TransportError> {
+        let mut now = ms(0);
+        let mut trace = Vec::new();
+
+        while !sender.is_done() {
+            match sender.poll(now)? {
+                Some(mut packet) => {
+                    tamper(&mut packet);
+                    trace.push(packet);
+
+                    if let Some(reply) = receiver.on_packet(&packet, now)? {
+                        trace.push(reply);
+                        sender.on_packet(&reply, now)?;
+                    }
+                }
+                None => now += BAM_INTERVAL,
+            }
+        }
+
+        Ok((trace, now))
+    }
+
+    #[test]
+    fn bam() {
+        let data = payload(20);
+        let mut buffer = [0; 64];
+
+        let mut sender = TransportSender::new(0xFECA, &data, 0x10, GLOBAL_ADDRESS).unwrap();
+        let mut receiver = TransportReceiver::new(&mut buffer, 0x20);
+
+        let (trace, now) = exchange(&mut sender, &mut receiver, |_| ()).unwrap();
+
+        assert_eq!(trace.len(), 4);
+        assert_eq!(trace[0].id.raw(), 0x1CEC_FF10);
+        assert_eq!(trace[0].data(), &[32, 20, 0, 3, 0xFF, 0xCA, 0xFE, 0x00]);
+        assert_eq!(trace[1].id.raw(), 0x1CEB_FF10);
+        assert_eq!(trace[1].data(), &[1, 0, 1, 2, 3, 4, 5, 6]);
+        // The last packet is padded
+        assert_eq!(trace[3].data(), &[3, 14, 15, 16, 17, 18, 19, 0xFF]);
+        // Packets are sent 50 ms apart
+        assert_eq!(now, ms(150));
+
+        let message = receiver.poll(now).unwrap().unwrap();
+
+        assert_eq!(
+            message,
+            Message {
+                pgn: 0xFECA,
+                source: 0x10,
+                destination: GLOBAL_ADDRESS,
+                len: 20,
+            }
+        );
+        assert_eq!(receiver.data(&message), &data[..]);
+        assert_eq!(receiver.poll(now), Ok(None));
+    }
+
+    #[test]
+    fn bam_times_out() {
+        let data = payload(20);
+        let mut buffer = [0; 64];
+
+        let mut sender = TransportSender::new(0xFECA, &data, 0x10, GLOBAL_ADDRESS).unwrap();
+        let mut receiver = TransportReceiver::new(&mut buffer, 0x20);
+
+        let announce = sender.poll(ms(0)).unwrap().unwrap();
+        receiver.on_packet(&announce, ms(0)).unwrap();
+
+        let first = sender.poll(ms(50)).unwrap().unwrap();
+        receiver.on_packet(&first, ms(50)).unwrap();
+
+        assert_eq!(receiver.poll(ms(50) + T1 - ms(1)), Ok(None));
+        assert_eq!(receiver.poll(ms(50) + T1), Err(TransportError::Timeout));
+    }
+
+    #[test]
+    fn bam_overflow() {
+        let data = payload(20);
+        let mut buffer = [0; 16];
+
+        let mut sender = TransportSender::new(0xFECA, &data, 0x10, GLOBAL_ADDRESS).unwrap();
+        let mut receiver = TransportReceiver::new(&mut buffer, 0x20);
+
+        let announce = sender.poll(ms(0)).unwrap().unwrap();
+
+        assert_eq!(
+            receiver.on_packet(&announce, ms(0)),
+            Err(TransportError::Overflow)
+        );
+    }
+
+    #[test]
+    fn rts_cts() {
+        let data = payload(100);
+        let mut buffer = [0; 128];
+
+        let mut sender = TransportSender::new(0xEF00, &data, 0x10, 0x20).unwrap();
+        let mut receiver = TransportReceiver::new(&mut buffer, 0x20);
+
+        let (trace, _) = exchange(&mut sender, &mut receiver, |_| ()).unwrap();
+
+        // RTS, CTS, 15 data packets and the end of message acknowledgement
+        assert_eq!(trace.len(), 18);
+        assert_eq!(trace[0].id.raw(), 0x1CEC_2010);
+        assert_eq!(trace[0].data(), &[16, 100, 0, 15, 0xFF, 0x00, 0xEF, 0x00]);
+        assert_eq!(trace[1].id.raw(), 0x1CEC_1020);
+        assert_eq!(trace[1].data(), &[17, 15, 1, 0xFF, 0xFF, 0x00, 0xEF, 0x00]);
+        assert_eq!(trace[2].id.raw(), 0x1CEB_2010);
+        assert_eq!(trace[17].data(), &[19, 100, 0, 15, 0xFF, 0x00, 0xEF, 0x00]);
+
+        let message = receiver.poll(ms(0)).unwrap().unwrap();
+
+        assert_eq!(message.source, 0x10);
+        assert_eq!(message.destination, 0x20);
+        assert_eq!(receiver.data(&message), &data[..]);
+    }
+
+    #[test]
+    fn rts_cts_windows() {
+        let data = payload(100);
+        let mut buffer = [0; 128];
+
+        let mut sender = TransportSender::new(0xEF00, &data, 0x10, 0x20).unwrap();
+        let mut receiver = TransportReceiver::new(&mut buffer, 0x20);
+
+        // Announce that at most 4 packets may be sent per CTS
+        let (trace, _) = exchange(&mut sender, &mut receiver, |packet| {
+            if packet.id.pgn == PGN_TP_CM && packet.data[0] == TP_CM_RTS {
+                packet.data[4] = 4;
+            }
+        })
+        .unwrap();
+
+        let clear_to_send = trace
+            .iter()
+            .filter(|packet| packet.id.source == 0x20 && packet.data[0] == TP_CM_CTS)
+            .map(|packet| (packet.data[1], packet.data[2]))
+            .collect::<Vec<_>>();
+
+        assert_eq!(clear_to_send, [(4, 1), (4, 5), (4, 9), (3, 13)]);
+
+        let message = receiver.poll(ms(0)).unwrap().unwrap();
+
+        assert_eq!(receiver.data(&message), &data[..]);
+    }
+
+    #[test]
+    fn rts_rejected_when_too_long() {
+        let data = payload(100);
+        let mut buffer = [0; 64];
+
+        let mut sender = TransportSender::new(0xEF00, &data, 0x10, 0x20).unwrap();
+        let mut receiver = TransportReceiver::new(&mut buffer, 0x20);
+
+        assert_eq!(
+            exchange(&mut sender, &mut receiver, |_| ()),
+            Err(TransportError::Aborted(ABORT_RESOURCES))
+        );
+    }
+
+    #[test]
+    fn rts_rejected_when_busy() {
+        let data = payload(100);
+        let mut buffer = [0; 128];
+
+        let mut first = TransportSender::new(0xEF00, &data, 0x10, 0x20).unwrap();
+        let mut second = TransportSender::new(0xEF00, &data, 0x11, 0x20).unwrap();
+        let mut receiver = TransportReceiver::new(&mut buffer, 0x20);
+
+        let rts = first.poll(ms(0)).unwrap().unwrap();
+        receiver.on_packet(&rts, ms(0)).unwrap().unwrap();
+
+        let rts = second.poll(ms(0)).unwrap().unwrap();
+        let abort = receiver.on_packet(&rts, ms(0)).unwrap().unwrap();
+
+        assert_eq!(abort.id.destination, 0x11);
+        assert_eq!(
+            second.on_packet(&abort, ms(0)),
+            Err(TransportError::Aborted(ABORT_BUSY))
+        );
+    }
+
+    #[test]
+    fn data_out_of_sequence() {
+        let data = payload(100);
+        let mut buffer = [0; 128];
+
+        let mut sender = TransportSender::new(0xEF00, &data, 0x10, 0x20).unwrap();
+        let mut receiver = TransportReceiver::new(&mut buffer, 0x20);
+
+        assert_eq!(
+            exchange(&mut sender, &mut receiver, |packet| {
+                if packet.id.pgn == PGN_TP_DT && packet.data[0] == 3 {
+                    packet.data[0] = 4;
+                }
+            }),
+            Err(TransportError::InvalidSequence)
+        );
+    }
+
+    #[test]
+    fn sender_times_out_without_clear_to_send() {
+        let data = payload(100);
+        let mut sender = TransportSender::new(0xEF00, &data, 0x10, 0x20).unwrap();
+
+        sender.poll(ms(0)).unwrap().unwrap();
+
+        assert_eq!(sender.poll(T3 - ms(1)), Ok(None));
+        assert_eq!(sender.poll(T3), Err(TransportError::Timeout));
+    }
+
+    #[test]
+    fn invalid_lengths() {
+        assert!(TransportSender::new(0xEF00, &[0; 8], 0x10, 0x20).is_err());
+        assert!(TransportSender::new(0xEF00, &[0; MAX_TRANSPORT_LEN + 1], 0x10, 0x20).is_err());
+        assert!(TransportSender::new(0xEF00, &[0; MAX_TRANSPORT_LEN], 0x10, 0x20).is_ok());
+    }
+}
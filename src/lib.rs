@@ -1,4 +1,4 @@
-#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
 #![feature(cfg_version)]
 #![feature(generic_associated_types)] // For mutex
 #![cfg_attr(not(version("1.59")), feature(asm))]
@@ -16,32 +16,84 @@ compile_error!("Feature `ulp` is currently only supported on esp32s2");
 #[macro_use]
 pub mod riscv_ulp_hal;
 
+#[cfg(any(feature = "esp-idf-sys", feature = "riscv-ulp-hal"))]
 pub mod adc;
-#[cfg(not(feature = "riscv-ulp-hal"))]
+#[cfg(all(feature = "esp-idf-sys", not(feature = "riscv-ulp-hal")))]
 pub mod can;
-#[cfg(all(feature = "experimental", not(feature = "riscv-ulp-hal")))]
-pub mod cpu;
 #[cfg(not(feature = "riscv-ulp-hal"))]
+pub mod canlog;
+#[cfg(all(
+    feature = "experimental",
+    feature = "esp-idf-sys",
+    not(feature = "riscv-ulp-hal")
+))]
+pub mod cpu;
+#[cfg(all(
+    not(esp32c3),
+    not(esp32s3),
+    feature = "esp-idf-sys",
+    not(feature = "riscv-ulp-hal")
+))]
+pub mod dac;
+#[cfg(all(feature = "esp-idf-sys", not(feature = "riscv-ulp-hal")))]
 pub mod delay;
+#[cfg(any(feature = "esp-idf-sys", feature = "riscv-ulp-hal"))]
 pub mod gpio;
 #[cfg(esp32)]
 pub mod hall;
-#[cfg(not(feature = "riscv-ulp-hal"))]
+#[cfg(all(feature = "esp-idf-sys", not(feature = "riscv-ulp-hal")))]
 pub mod i2c;
-#[cfg(all(feature = "experimental", not(feature = "riscv-ulp-hal")))]
+#[cfg(all(esp32, not(feature = "riscv-ulp-hal")))]
+pub mod i2s;
+#[cfg(all(
+    feature = "experimental",
+    feature = "esp-idf-sys",
+    not(feature = "riscv-ulp-hal")
+))]
 pub mod interrupt;
 #[cfg(not(feature = "riscv-ulp-hal"))]
+pub mod isotp;
+#[cfg(not(feature = "riscv-ulp-hal"))]
+pub mod j1939;
+#[cfg(all(feature = "esp-idf-sys", not(feature = "riscv-ulp-hal")))]
 pub mod mutex;
+#[cfg(any(feature = "esp-idf-sys", feature = "riscv-ulp-hal"))]
 pub mod peripherals;
+#[cfg(any(feature = "esp-idf-sys", feature = "riscv-ulp-hal"))]
 pub mod prelude;
-#[cfg(not(feature = "riscv-ulp-hal"))]
+#[cfg(all(feature = "esp-idf-sys", not(feature = "riscv-ulp-hal")))]
 pub mod serial;
-#[cfg(not(feature = "riscv-ulp-hal"))]
+#[cfg(all(feature = "esp-idf-sys", not(feature = "riscv-ulp-hal")))]
 pub mod spi;
 #[cfg(all(any(esp32, esp32s2, esp32s3), not(feature = "riscv-ulp-hal")))]
 pub mod ulp;
 pub mod units;
 
+// Without ESP-IDF, only the logic which does not touch the hardware is built,
+// so that its tests can run on the host:
+//
+// cargo test --no-default-features --lib
+#[cfg(not(any(feature = "esp-idf-sys", feature = "riscv-ulp-hal")))]
+pub mod adc {
+    pub mod calibration;
+}
+
+#[cfg(not(any(feature = "esp-idf-sys", feature = "riscv-ulp-hal")))]
+pub mod can {
+    pub mod config {
+        mod filter;
+        mod timing;
+
+        pub use filter::Filter;
+        pub use timing::Timing;
+    }
+}
+
+#[cfg(not(any(feature = "esp-idf-sys", feature = "riscv-ulp-hal")))]
+pub mod i2c {
+    pub mod register;
+}
+
 #[cfg(feature = "riscv-ulp-hal")]
 pub use crate::riscv_ulp_hal::delay;
 
@@ -30,6 +30,11 @@ pub const adc_atten_t_ADC_ATTEN_DB_6: adc_atten_t = 2;
 #[allow(non_upper_case_globals)]
 pub const adc_atten_t_ADC_ATTEN_DB_11: adc_atten_t = 3;
 
+#[allow(non_upper_case_globals)]
+pub const dac_channel_t_DAC_CHANNEL_1: dac_channel_t = 0;
+#[allow(non_upper_case_globals)]
+pub const dac_channel_t_DAC_CHANNEL_2: dac_channel_t = 1;
+
 #[allow(non_upper_case_globals)]
 pub const gpio_mode_t_GPIO_MODE_DISABLE: u8 = 0;
 #[allow(non_upper_case_globals)]